@@ -1,6 +1,14 @@
 mod analyze;
 mod caff;
+mod csv;
+mod json;
 mod load;
+mod partial;
+mod progress;
+
+use std::time::Duration;
+
+const TIMEOUT_EXIT_CODE: i32 = 124;
 
 #[derive(Debug, Clone, clap::Parser)]
 #[remain::sorted]
@@ -9,6 +17,15 @@ pub struct Command {
   pub log_level: LogLevel,
   #[command(subcommand)]
   subcommand: Subcommand,
+  #[arg(
+    long,
+    global = true,
+    value_name = "DURATION",
+    value_parser = humantime::parse_duration,
+    help = "abort with exit code 124 if the command runs longer than DURATION (e.g. 30s or 5m)",
+    long_help = "abort with exit code 124 if the command runs longer than DURATION (e.g. 30s or 5m). Partial files from caff extract --atomic are removed first, but anything else written so far (such as a --tar stream, or a file being written without --atomic) is left incomplete."
+  )]
+  timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
@@ -20,7 +37,16 @@ enum Subcommand {
 
 impl Command {
   pub fn execute(self) -> anyhow::Result<()> {
-    let Self { subcommand, .. } = self;
+    let Self { subcommand, timeout, .. } = self;
+
+    if let Some(timeout) = timeout {
+      std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        log::error!("aborting because the command ran longer than {}", humantime::format_duration(timeout));
+        let _partials = partial::PARTIAL_FILES.remove_all();
+        std::process::exit(TIMEOUT_EXIT_CODE);
+      });
+    }
 
     match subcommand {
       Subcommand::Analyze(command) => command.execute(),
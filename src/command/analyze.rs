@@ -1,18 +1,31 @@
+use super::caff;
 use log::{debug, info, trace};
 use orphism::Runtime;
+use std::collections::BTreeMap;
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, clap::Parser)]
 #[remain::sorted]
+#[command(group(clap::ArgGroup::new("source").args(["archive", "model_file", "runtime_dir"]).required(true).multiple(false)))]
 pub struct Analyze {
+  #[arg(long, value_name = "PATH", requires = "entry", help = "analyze a .moc3 stored inside a CAFF archive")]
+  archive: Option<PathBuf>,
+  #[arg(long, help = "print the set bits of each word in regions classified as small integers")]
+  bits: bool,
+  #[arg(long, help = "summarize the number of regions and bytes classified as each type")]
+  compare_types: bool,
   #[arg(long, default_value = "little")]
   endian: Endian,
-  #[arg(long, conflicts_with = "runtime_dir")]
+  #[arg(long, value_name = "NAME", requires = "archive", help = "name of the archive entry to analyze (decompressed if zipped)")]
+  entry: Option<String>,
+  #[arg(long)]
   model_file: Option<PathBuf>,
+  #[arg(long, help = "guess the record size of each region by autocorrelation")]
+  guess_stride: bool,
   #[arg(long, default_value = "5")]
   report_offset: u64,
-  #[arg(long, conflicts_with = "model_file")]
+  #[arg(long)]
   runtime_dir: Option<PathBuf>,
   #[arg(long, default_value = "0")]
   start_at: u64,
@@ -28,73 +41,139 @@ enum Endian {
 impl Analyze {
   pub fn execute(self) -> anyhow::Result<()> {
     let Self {
+      archive,
+      bits,
+      compare_types,
       endian,
+      entry,
+      guess_stride,
       model_file: model,
       report_offset,
       runtime_dir: runtime,
       start_at,
     } = self;
 
-    let runtime = match (model, runtime) {
-      (Some(_), Some(_)) => panic!("Cannot provide both model and runtime path. CLI argument validation should have prevented this. (╯°□°)╯︵ ┻━┻"),
-      (None, None) => panic!("Missing either model or runtime path."),
-      (Some(path), None) => Runtime::new_from_model_path(path)?,
-      (None, Some(path)) => Runtime::new_from_runtime_path(path)?,
+    let data = match (model, runtime, archive, entry) {
+      (None, None, Some(path), Some(name)) => caff::read_entry(&path, &name)?,
+      (Some(path), None, None, _) => Runtime::new_from_model_path(path)?.load_model()?.data,
+      (None, Some(path), None, _) => Runtime::new_from_runtime_path(path)?.load_model()?.data,
+      _ => unreachable!("Exactly one of model, runtime, or archive path is required. CLI argument validation should have prevented this. (╯°□°)╯︵ ┻━┻"),
     };
 
-    let model = runtime.load_model()?;
-
-    let mut moc3 = Cursor::new(model.data);
-    moc3.seek(SeekFrom::Start(start_at))?;
-    let mut buf = [0u8; 4];
-
-    let mut data_run = 0u64;
-    let mut data_start = moc3.stream_position()?;
-    let mut zero_run = 0u64;
-    let mut zero_start = moc3.stream_position()?;
-
-    let mut data = Vec::<[u8; 4]>::new();
-    let mut last = moc3.stream_position()?;
-
-    while let Ok(()) = moc3.read_exact(&mut buf) {
-      if buf == [0, 0, 0, 0] {
-        if zero_run == 0 {
-          zero_start = last;
-        } else {
-          if data_run > 0 {
-            let (assumed, min, max, maybe_float, maybe_string) = infer(&data, endian);
-            info!(
-              "DATA {:#010x?} {:#010x?} size={} probably={assumed} min={min} max={max} maybe_float={maybe_float} maybe_string={maybe_string}",
-              data_start,
-              last - report_offset,
-              data_run * 4
-            );
+    let regions = scan(data, start_at)?;
+
+    for region in &regions {
+      match region {
+        Region::Void { start, end, size } => debug!("VOID {:#010x?} {:#010x?} size={size}", start, end - report_offset),
+        Region::Data { start, end, words } => {
+          let (assumed, confidence, min, max, maybe_float, maybe_string) = infer(words, endian);
+          info!(
+            "DATA {:#010x?} {:#010x?} size={} probably={assumed} confidence={confidence:.2} min={min} max={max} maybe_float={maybe_float} maybe_string={maybe_string}",
+            start,
+            end - report_offset,
+            words.len() * 4
+          );
+          if bits && assumed.is_small_integer() {
+            for (index, bytes) in words.iter().enumerate() {
+              let word = match endian {
+                Endian::Big => u32::from_be_bytes(*bytes),
+                Endian::Little => u32::from_le_bytes(*bytes),
+              };
+              info!("BITS {:#010x?}[{index}] {word:#034b} set={:?}", start, set_bits(word));
+            }
           }
-          data_run = 0;
-        }
-        zero_run += 1;
-      } else {
-        if data_run == 0 {
-          data_start = last;
-          data.clear();
-        } else {
-          if zero_run >= 8 {
-            debug!("VOID {:#010x?} {:#010x?} size={}", zero_start, last - report_offset, zero_run * 4);
+          if guess_stride {
+            if let Some((stride, confidence)) = autocorrelate(words) {
+              info!("STRIDE {:#010x?} stride={stride} confidence={confidence:.2}", start);
+            }
           }
-          zero_run = 0;
         }
-        data.push(buf);
-        data_run += 1
       }
+    }
 
-      last = moc3.stream_position()?;
+    if compare_types {
+      for (assumed, (regions, bytes)) in summarize(&regions, endian) {
+        info!("TYPE {assumed} regions={regions} size={bytes}");
+      }
     }
 
     Ok(())
   }
 }
 
-fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool) {
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Region {
+  Data { start: u64, end: u64, words: Vec<[u8; 4]> },
+  Void { start: u64, end: u64, size: u64 },
+}
+
+fn scan(data: Vec<u8>, start_at: u64) -> anyhow::Result<Vec<Region>> {
+  let mut moc3 = Cursor::new(data);
+  moc3.seek(SeekFrom::Start(start_at))?;
+  let mut buf = [0u8; 4];
+
+  let mut data_run = 0u64;
+  let mut data_start = moc3.stream_position()?;
+  let mut zero_run = 0u64;
+  let mut zero_start = moc3.stream_position()?;
+
+  let mut data = Vec::<[u8; 4]>::new();
+  let mut last = moc3.stream_position()?;
+  let mut regions = Vec::new();
+
+  while let Ok(()) = moc3.read_exact(&mut buf) {
+    if buf == [0, 0, 0, 0] {
+      if zero_run == 0 {
+        zero_start = last;
+      } else {
+        if data_run > 0 {
+          regions.push(Region::Data {
+            start: data_start,
+            end: last,
+            words: std::mem::take(&mut data),
+          });
+        }
+        data_run = 0;
+      }
+      zero_run += 1;
+    } else {
+      if data_run == 0 {
+        data_start = last;
+        data.clear();
+      } else {
+        if zero_run >= 8 {
+          regions.push(Region::Void {
+            start: zero_start,
+            end: last,
+            size: zero_run * 4,
+          });
+        }
+        zero_run = 0;
+      }
+      data.push(buf);
+      data_run += 1
+    }
+
+    last = moc3.stream_position()?;
+  }
+
+  Ok(regions)
+}
+
+fn summarize(regions: &[Region], endian: Endian) -> BTreeMap<AssumedType, (u64, u64)> {
+  let mut summary = BTreeMap::<AssumedType, (u64, u64)>::new();
+  for region in regions {
+    if let Region::Data { words, .. } = region {
+      let (assumed, ..) = infer(words, endian);
+      let (regions, bytes) = summary.entry(assumed).or_default();
+      *regions += 1;
+      *bytes += words.len() as u64 * 4;
+    }
+  }
+  summary
+}
+
+fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, f64, i64, i64, bool, bool) {
   let mut all = Vec::new();
   let mut min = 0i64;
   let mut max = 0i64;
@@ -102,6 +181,13 @@ fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool
   let mut max_f = 0.0f32;
   let mut float = true;
 
+  let mut bool_like = true;
+  let mut i8_like = true;
+  let mut i16_like = true;
+  let mut u8_like = true;
+  let mut u16_like = true;
+  let mut f32_like = true;
+
   for bytes in data {
     {
       let number = match endian {
@@ -112,6 +198,9 @@ fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool
 
       trace!("{number} as {endian} signed");
 
+      i8_like &= i8::try_from(number).is_ok();
+      i16_like &= i16::try_from(number).is_ok();
+
       if number < min {
         min = number;
       }
@@ -130,6 +219,10 @@ fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool
 
       trace!("{number} as {endian} unsigned");
 
+      bool_like &= number <= 1;
+      u8_like &= u8::try_from(number).is_ok();
+      u16_like &= u16::try_from(number).is_ok();
+
       if number < min {
         min = number;
       }
@@ -147,6 +240,8 @@ fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool
 
       trace!("{number} as {endian} float");
 
+      f32_like &= plausible_float(number.into());
+
       if number < min_f {
         min_f = number;
       }
@@ -189,14 +284,85 @@ fn infer(data: &[[u8; 4]], endian: Endian) -> (AssumedType, i64, i64, bool, bool
     AssumedType::Zero
   };
 
-  (assumed_type, min, max, float, string)
+  let f64_like = data.len().is_multiple_of(2)
+    && data.chunks_exact(2).all(|pair| {
+      let mut bytes = [0u8; 8];
+      bytes[..4].copy_from_slice(&pair[0]);
+      bytes[4..].copy_from_slice(&pair[1]);
+      let number = match endian {
+        Endian::Big => f64::from_be_bytes(bytes),
+        Endian::Little => f64::from_le_bytes(bytes),
+      };
+      plausible_float(number)
+    });
+
+  // any four bytes are a valid 32-bit integer, so a wide integer classification is only worth refining into a float
+  let assumed_type = match assumed_type {
+    AssumedType::I32 | AssumedType::U32 if f32_like => AssumedType::F32,
+    AssumedType::I32 | AssumedType::U32 if f64_like => AssumedType::F64,
+    assumed_type => assumed_type,
+  };
+
+  let candidates = [
+    (AssumedType::Bool, bool_like),
+    (AssumedType::F32, f32_like),
+    (AssumedType::F64, f64_like),
+    (AssumedType::I8, i8_like),
+    (AssumedType::I16, i16_like),
+    (AssumedType::U8, u8_like),
+    (AssumedType::U16, u16_like),
+  ];
+  // wider types can never be ruled out by data that fits the assumed type, so they say nothing about how good a fit it is
+  let alternatives = candidates.iter().filter(|(candidate, _)| *candidate != assumed_type && !candidate.contains(assumed_type));
+  let ruled_out = alternatives.clone().filter(|(_, plausible)| !plausible).count();
+  let confidence = match assumed_type {
+    AssumedType::Zero => 1.0,
+    // any four bytes are a valid 32-bit integer, so these are what is left when nothing else fits
+    AssumedType::I32 | AssumedType::U32 => 0.0,
+    _ => ruled_out as f64 / alternatives.count() as f64,
+  };
+
+  (assumed_type, confidence, min, max, float, string)
+}
+
+const FLOAT_MIN: f64 = 1e-6;
+const FLOAT_MAX: f64 = 1e6;
+
+fn plausible_float(number: f64) -> bool {
+  number == 0.0 || (FLOAT_MIN..=FLOAT_MAX).contains(&number.abs())
+}
+
+fn set_bits(word: u32) -> Vec<u32> {
+  (0..u32::BITS).filter(|bit| word & (1 << bit) != 0).collect()
 }
 
-#[derive(Debug, Clone, Copy, strum::Display)]
+const MAX_STRIDE: usize = 256;
+const STRIDE_TOLERANCE: f64 = 0.05;
+
+fn autocorrelate(data: &[[u8; 4]]) -> Option<(usize, f64)> {
+  let bytes: Vec<u8> = data.iter().flatten().copied().collect();
+  let limit = (bytes.len() / 2).min(MAX_STRIDE);
+
+  let scores: Vec<(usize, f64)> = (1..=limit)
+    .map(|period| {
+      let matches = bytes.iter().zip(&bytes[period..]).filter(|(a, b)| a == b).count();
+      (period, matches as f64 / (bytes.len() - period) as f64)
+    })
+    .collect();
+
+  let best = scores.iter().map(|(_, score)| *score).fold(0.0, f64::max);
+
+  // multiples of the true stride correlate just as well, so prefer the shortest period that is (nearly) as good as the best
+  scores.into_iter().find(|(_, score)| *score >= best - STRIDE_TOLERANCE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, strum::Display)]
 #[allow(non_camel_case_types)]
 #[remain::sorted]
 enum AssumedType {
   Bool,
+  F32,
+  F64,
   I8,
   I16,
   I32,
@@ -205,3 +371,121 @@ enum AssumedType {
   U32,
   Zero,
 }
+
+impl AssumedType {
+  fn is_small_integer(self) -> bool {
+    matches!(self, Self::Bool | Self::I8 | Self::I16 | Self::U8 | Self::U16)
+  }
+
+  fn contains(self, other: Self) -> bool {
+    matches!(
+      (self, other),
+      (Self::I8 | Self::I16 | Self::U8 | Self::U16, Self::Bool) | (Self::I16, Self::I8 | Self::U8) | (Self::U16, Self::U8)
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::Parser;
+
+  fn words(values: &[u32]) -> Vec<[u8; 4]> {
+    values.iter().map(|value| value.to_le_bytes()).collect()
+  }
+
+  #[test]
+  fn requires_exactly_one_source() {
+    assert!(Analyze::try_parse_from(["analyze"]).is_err());
+    assert!(Analyze::try_parse_from([
+      "analyze",
+      "--model-file",
+      "model.moc3",
+      "--runtime-dir",
+      "runtime"
+    ])
+    .is_err());
+    assert!(Analyze::try_parse_from(["analyze", "--archive", "model.cmo3"]).is_err());
+    assert!(Analyze::try_parse_from(["analyze", "--model-file", "model.moc3"]).is_ok());
+  }
+
+  #[test]
+  fn set_bits_lists_bit_positions_from_least_significant() {
+    assert_eq!(set_bits(0), Vec::<u32>::new());
+    assert_eq!(set_bits(0b1), [0]);
+    assert_eq!(set_bits(0b1010_0100), [2, 5, 7]);
+    assert_eq!(set_bits(0x8000_0001), [0, 31]);
+  }
+
+  fn bytes(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+  }
+
+  #[test]
+  fn scan_splits_data_on_double_zero_words() {
+    let regions = scan(bytes(&[1, 0, 1, 0, 0, 300, 400, 0, 0, 7]), 0).unwrap();
+
+    assert_eq!(
+      regions,
+      [
+        Region::Data {
+          start: 0,
+          end: 16,
+          words: words(&[1, 1])
+        },
+        Region::Data {
+          start: 20,
+          end: 32,
+          words: words(&[300, 400])
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn scan_reports_long_zero_runs_as_voids() {
+    let regions = scan(bytes(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3]), 0).unwrap();
+
+    assert_eq!(regions[1], Region::Void { start: 4, end: 48, size: 40 });
+  }
+
+  #[test]
+  fn summary_counts_regions_and_bytes_per_type() {
+    let regions = scan(bytes(&[1, 0, 1, 0, 0, 300, 400, 0, 0, 1, 1, 0, 0, 7]), 0).unwrap();
+    let summary = summarize(&regions, Endian::Little);
+
+    assert_eq!(summary.into_iter().collect::<Vec<_>>(), [(AssumedType::Bool, (2, 16)), (AssumedType::U16, (1, 8))]);
+  }
+
+  #[test]
+  fn guessed_stride_matches_record_size() {
+    let records: Vec<[u8; 4]> = (0..30u8).map(|index| [index % 3 + 1, 0xA0 + index % 3, 0, 0]).collect();
+
+    assert_eq!(autocorrelate(&records).map(|(stride, _)| stride), Some(12));
+  }
+
+  #[test]
+  fn small_floats_are_f32_with_high_confidence() {
+    let data: Vec<[u8; 4]> = [0.5f32, 1.5, 0.25, 2.0].iter().map(|value| value.to_le_bytes()).collect();
+    let (assumed, confidence, ..) = infer(&data, Endian::Little);
+
+    assert_eq!(assumed, AssumedType::F32);
+    assert!(confidence >= 0.8, "confidence was {confidence}");
+  }
+
+  #[test]
+  fn flags_are_bool_with_full_confidence() {
+    let (assumed, confidence, ..) = infer(&words(&[1, 0, 1, 1]), Endian::Little);
+
+    assert_eq!(assumed, AssumedType::Bool);
+    assert_eq!(confidence, 1.0);
+  }
+
+  #[test]
+  fn wide_integers_have_no_confidence() {
+    let (assumed, confidence, ..) = infer(&words(&[0xFFFF_FFF0, 0x8000_0001, 0xDEAD_BEEF, 0x7FFF_FFFF]), Endian::Little);
+
+    assert!(matches!(assumed, AssumedType::I32 | AssumedType::U32), "assumed {assumed}");
+    assert_eq!(confidence, 0.0);
+  }
+}
@@ -1,8 +1,17 @@
-use orphism::caff::Archive;
+mod filter;
+
+use super::csv;
+use super::json;
+use super::partial::PARTIAL_FILES;
+use super::progress::Progress;
+use filter::{Fields, Filter};
+use orphism::caff::{Archive, Key};
+use sha2::{Digest, Sha256};
 use std::{
+  collections::{BTreeMap, HashMap},
   fs::File,
-  io::{Cursor, Write},
-  path::PathBuf,
+  io::{Cursor, Read, Write},
+  path::{Component, Path, PathBuf},
 };
 
 #[derive(Debug, Clone, clap::Parser)]
@@ -18,10 +27,12 @@ pub struct Caff {
 #[derive(Debug, Clone, clap::Subcommand)]
 #[remain::sorted]
 enum Subcommand {
-  // Decrypt(Decrypt),
+  Checksum(Checksum),
+  Decrypt(Decrypt),
   Extract(Extract),
   List(List),
   ShowKey(ShowKey),
+  Stat(Stat),
 }
 
 impl Caff {
@@ -32,10 +43,12 @@ impl Caff {
     let mut archive = Archive::read(&mut archive)?;
 
     match subcommand {
-      // Subcommand::Decrypt(command) => command.execute(&mut archive),
+      Subcommand::Checksum(command) => command.execute(&mut archive),
+      Subcommand::Decrypt(command) => command.execute(&mut archive),
       Subcommand::Extract(command) => command.execute(archive),
       Subcommand::List(command) => command.execute(&mut archive),
       Subcommand::ShowKey(command) => command.execute(&mut archive),
+      Subcommand::Stat(command) => command.execute(&mut archive),
     }
   }
 }
@@ -82,77 +95,483 @@ enum KeyFormat {
 struct List {
   #[arg(long = "no-header", short = 'H', action = clap::ArgAction::SetFalse, help = "skip printing column header")]
   header: bool,
+  #[arg(
+    long,
+    short,
+    conflicts_with_all = ["ratios", "sizes", "tags", "tree"],
+    help = "show index, zip marker, size, tag, and filename of each entry in aligned columns"
+  )]
+  long: bool,
+  #[arg(long, short, help = "include compression ratios in output (1.0 for entries that are not zipped)")]
+  ratios: bool,
   #[arg(long, short, help = "include file sizes in output")]
   sizes: bool,
   #[arg(long, short, help = "include tags in output")]
   tags: bool,
+  #[arg(long, conflicts_with_all = ["ratios", "sizes", "tags"], help = "show entries as a tree of their /-separated paths, with sizes")]
+  tree: bool,
 }
 
 impl List {
   fn execute(&self, archive: &mut Archive) -> anyhow::Result<()> {
-    let Self { header, sizes, tags } = self;
+    let Self {
+      header,
+      long,
+      ratios,
+      sizes,
+      tags,
+      tree,
+    } = self;
+
+    if *long {
+      let rows: Vec<[String; 5]> = archive
+        .body
+        .metadata
+        .iter()
+        .zip(archive.body.data.iter())
+        .enumerate()
+        .map(|(index, (metadata, data))| {
+          let zipped = if is_zip(data) { "z" } else { "-" };
+          let tag = if metadata.tag.is_empty() { "-" } else { metadata.tag.as_str() };
+          [
+            index.to_string(),
+            zipped.to_string(),
+            metadata.file_size.to_string(),
+            tag.to_string(),
+            metadata.file_name.to_string(),
+          ]
+        })
+        .collect();
+
+      let columns = ["INDEX", "ZIP", "SIZE", "TAG", "FILENAME"];
+      let mut widths = if *header { columns.map(str::len) } else { [0; 5] };
+      for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+          *width = (*width).max(column.len());
+        }
+      }
+
+      if *header {
+        print_long_row(columns, &widths);
+      }
+      for row in &rows {
+        print_long_row(row.each_ref().map(String::as_str), &widths);
+      }
+      return Ok(());
+    }
+
+    if *tree {
+      let mut root = Tree::default();
+      for metadata in archive.body.metadata.iter() {
+        root.insert(&metadata.file_name, metadata.file_size.to_string());
+      }
+      print!("{}", root.render());
+      return Ok(());
+    }
 
     if *header {
-      let header = match (sizes, tags) {
-        (false, false) => "FILENAME",
-        (true, false) => "FILENAME\tSIZE",
-        (false, true) => "FILENAME\tTAG",
-        (true, true) => "FILENAME\tSIZE\tTAG",
+      let mut columns = vec!["FILENAME"];
+      if *sizes {
+        columns.push("SIZE");
+      }
+      if *ratios {
+        columns.push("RATIO");
+      }
+      if *tags {
+        columns.push("TAG");
+      }
+      println!("{}", columns.join("\t"));
+    }
+
+    for (metadata, data) in archive.body.metadata.iter().zip(archive.body.data.iter()) {
+      let mut columns = vec![metadata.file_name.to_string()];
+      if *sizes {
+        columns.push(metadata.file_size.to_string());
+      }
+      if *ratios {
+        columns.push(format!("{:.2}", compression_ratio(data)));
+      }
+      if *tags && !metadata.tag.is_empty() {
+        columns.push(metadata.tag.to_string());
+      }
+      println!("{}", columns.join("\t"));
+    }
+
+    Ok(())
+  }
+}
+
+fn print_long_row([index, zipped, size, tag, name]: [&str; 5], widths: &[usize; 5]) {
+  let [index_width, zipped_width, size_width, tag_width, _] = *widths;
+  println!("{index:>index_width$}  {zipped:<zipped_width$}  {size:>size_width$}  {tag:<tag_width$}  {name}");
+}
+
+#[derive(Debug, Default)]
+struct Tree<'a> {
+  children: BTreeMap<&'a str, Tree<'a>>,
+  size: Option<String>,
+}
+
+impl<'a> Tree<'a> {
+  fn insert(&mut self, path: &'a str, size: String) {
+    let node = path
+      .split('/')
+      .filter(|component| !component.is_empty())
+      .fold(self, |node, component| node.children.entry(component).or_default());
+    node.size = Some(size);
+  }
+
+  fn render(&self) -> String {
+    let mut output = String::new();
+    self.render_into(&mut output, 0);
+    output
+  }
+
+  fn render_into(&self, output: &mut String, depth: usize) {
+    let indent = "  ".repeat(depth);
+    for (name, node) in &self.children {
+      match &node.size {
+        Some(size) if node.children.is_empty() => output.push_str(&format!("{indent}{name} ({size})\n")),
+        _ => output.push_str(&format!("{indent}{name}/\n")),
+      }
+      node.render_into(output, depth + 1);
+    }
+  }
+}
+
+fn compression_ratio(data: &[u8]) -> f64 {
+  match zip_payload(data) {
+    Some(payload) if payload.method == ZIP_METHOD_STORED => 1.0,
+    Some(payload) => match payload.uncompressed_size(data) {
+      Some(uncompressed_size) if uncompressed_size > 0 => payload.compressed_size as f64 / uncompressed_size as f64,
+      _ => 1.0,
+    },
+    None => 1.0,
+  }
+}
+
+pub(crate) fn unzip(data: &[u8]) -> Option<Vec<u8>> {
+  let mut reader = Cursor::new(data);
+  let entry = synthzip::Entry::read(&mut reader).ok()?;
+  entry.decompress().ok()
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[remain::sorted]
+#[clap(about = "show details about a single entry in a CAFF archive")]
+struct Stat {
+  #[arg(long, help = "print JSON on a single line instead of pretty-printed (with --format json)")]
+  compact: bool,
+  #[arg(value_name = "ENTRY", help = "filename of the entry to describe")]
+  entry: String,
+  #[arg(long, short, default_value = "text", help = "format to print details in")]
+  format: StatFormat,
+}
+
+impl Stat {
+  fn execute(&self, archive: &mut Archive) -> anyhow::Result<()> {
+    let Self { compact, entry, format } = self;
+
+    let (index, (metadata, data)) = archive
+      .body
+      .metadata
+      .iter()
+      .zip(archive.body.data.iter())
+      .enumerate()
+      .find(|(_, (metadata, _))| metadata.file_name == *entry)
+      .ok_or_else(|| anyhow::anyhow!("archive does not contain an entry named {entry:?}"))?;
+
+    let payload = zip_payload(data);
+
+    let details = serde_json::json!({
+      "index": index,
+      "file_name": metadata.file_name,
+      "tag": metadata.tag,
+      "declared_size": metadata.file_size,
+      "actual_size": data.len(),
+      "zipped": is_zip(data),
+      "method": payload.map(|payload| payload.method),
+      "unpacked_size": payload.and_then(|payload| payload.uncompressed_size(data)),
+      "ratio": compression_ratio(data),
+      "sha256": sha256(data),
+    });
+
+    match format {
+      StatFormat::Json => println!("{}", json::format(&details, *compact)),
+      StatFormat::Text => {
+        if let Some(details) = details.as_object() {
+          for (key, value) in details {
+            match value {
+              serde_json::Value::String(value) => println!("{key}: {value}"),
+              value => println!("{key}: {value}"),
+            }
+          }
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[clap(rename_all = "lowercase")]
+#[remain::sorted]
+enum StatFormat {
+  Json,
+  Text,
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[remain::sorted]
+#[clap(about = "write or verify SHA-256 checksums of the entries in a CAFF archive")]
+struct Checksum {
+  #[arg(long, short, value_name = "FILE", conflicts_with = "verify", help = "write checksums to a file instead of stdout")]
+  output: Option<PathBuf>,
+  #[arg(long, value_name = "FILE", help = "verify entries against a previously written checksum file")]
+  verify: Option<PathBuf>,
+}
+
+impl Checksum {
+  fn execute(&self, archive: &mut Archive) -> anyhow::Result<()> {
+    let Self { output, verify } = self;
+
+    if let Some(manifest) = verify {
+      let contents = std::fs::read_to_string(manifest)?;
+      let entries = archive
+        .body
+        .metadata
+        .iter()
+        .zip(archive.body.data.iter())
+        .map(|(metadata, data)| (metadata.file_name.as_str(), data.as_slice()));
+
+      let mut failures = 0usize;
+      for (name, status) in Self::verify(entries, &contents)? {
+        if status != ChecksumStatus::Ok {
+          failures += 1;
+        }
+        println!("{name}: {status}");
+      }
+
+      if failures > 0 {
+        anyhow::bail!("{failures} entries did not match {manifest:?}");
+      }
+
+      return Ok(());
+    }
+
+    let mut checksums = String::new();
+    for (metadata, data) in archive.body.metadata.iter().zip(archive.body.data.iter()) {
+      checksums.push_str(&format!("{}  {}\n", sha256(data), metadata.file_name));
+    }
+
+    match output {
+      Some(path) => std::fs::write(path, checksums)?,
+      None => print!("{checksums}"),
+    }
+
+    Ok(())
+  }
+
+  fn verify<'a>(entries: impl IntoIterator<Item = (&'a str, &'a [u8])>, manifest: &str) -> anyhow::Result<Vec<(String, ChecksumStatus)>> {
+    let mut expected = BTreeMap::new();
+    for line in manifest.lines().filter(|line| !line.trim().is_empty()) {
+      let Some((hash, name)) = line.split_once("  ") else {
+        anyhow::bail!("invalid line in checksum file (expected HASH  NAME): {line:?}");
       };
-      println!("{header}");
+      expected.insert(name, hash);
     }
 
-    for metadata in archive.body.metadata.iter() {
-      let entry = match (sizes, tags) {
-        (false, false) => format!("{}", metadata.file_name),
-        (true, false) => format!("{}\t{}", metadata.file_name, metadata.file_size),
-        (false, true) if metadata.tag.is_empty() => format!("{}", metadata.file_name),
-        (false, true) => format!("{}\t{}", metadata.file_name, metadata.tag),
-        (true, true) if metadata.tag.is_empty() => format!("{}\t{}", metadata.file_name, metadata.file_size),
-        (true, true) => format!("{}\t{}\t{}", metadata.file_name, metadata.file_size, metadata.tag),
+    let mut statuses = Vec::new();
+
+    for (name, data) in entries {
+      let status = match expected.remove(name) {
+        Some(hash) if hash.eq_ignore_ascii_case(&sha256(data)) => ChecksumStatus::Ok,
+        Some(_) => ChecksumStatus::Failed,
+        None => ChecksumStatus::New,
       };
-      println!("{entry}");
+      statuses.push((name.to_string(), status));
     }
 
-    Ok(())
+    statuses.extend(expected.into_keys().map(|name| (name.to_string(), ChecksumStatus::Missing)));
+
+    Ok(statuses)
   }
 }
 
-// #[derive(Debug, Clone, clap::Parser)]
-// #[remain::sorted]
-// struct Decrypt {
-//   #[arg(long, short, value_name = "FILE")]
-//   output: PathBuf,
-//   #[arg(long, short, value_name = "KEY")]
-//   recrypt: Option<u32>,
-// }
-
-// impl Decrypt {
-//   pub fn execute(&self, archive: &mut Archive) -> anyhow::Result<()> {
-//     let Self { output, recrypt } = self;
-//     let key = recrypt.map(Key::from).unwrap_or_default();
-//     archive.header.key = key;
-//     if let Some(parent) = output.parent() {
-//       if !parent.exists() {
-//         std::fs::create_dir_all(parent)?;
-//       }
-//     }
-//     let mut file = File::create(output)?;
-//     archive.write(&mut file)?;
-//     file.flush()?;
-//     Ok(())
-//   }
-// }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "UPPERCASE")]
+#[remain::sorted]
+enum ChecksumStatus {
+  Failed,
+  Missing,
+  New,
+  Ok,
+}
+
+fn sha256(data: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(data))
+}
+
+pub(crate) fn read_entry(path: &Path, name: &str) -> anyhow::Result<Vec<u8>> {
+  let mut archive = File::open(path)?;
+  let archive = Archive::read(&mut archive)?;
+
+  let data = archive
+    .body
+    .metadata
+    .iter()
+    .zip(archive.body.data)
+    .find_map(|(metadata, data)| (metadata.file_name == name).then_some(data))
+    .ok_or_else(|| anyhow::anyhow!("archive does not contain an entry named {name:?}"))?;
+
+  Ok(unzip(&data).unwrap_or(data))
+}
+
+#[derive(Debug, Clone, clap::Parser)]
+#[remain::sorted]
+#[clap(about = "write a decrypted (or re-encrypted) copy of a CAFF archive")]
+struct Decrypt {
+  #[arg(long, short, value_name = "FILE", help = "path to write the decrypted archive to")]
+  output: PathBuf,
+  #[arg(long, short, value_name = "KEY", value_parser = parse_key, help = "encrypt the copy with KEY instead (decimal, or prefixed with 0x or 0b)")]
+  recrypt: Option<u32>,
+}
+
+impl Decrypt {
+  fn execute(&self, archive: &mut Archive) -> anyhow::Result<()> {
+    let Self { output, recrypt } = self;
+
+    archive.header.key = recrypt.map(Key::from).unwrap_or_default();
+
+    if let Some(parent) = output.parent() {
+      if !parent.exists() {
+        std::fs::create_dir_all(parent)?;
+      }
+    }
+
+    let mut file = File::create(output)?;
+    archive.write(&mut file)?;
+    file.flush()?;
+
+    Ok(())
+  }
+}
 
 #[derive(Debug, Clone, clap::Parser)]
 #[clap(about = "extract files from a CAFF archive")]
 struct Extract {
+  #[arg(long, help = "write each file to a temporary name and rename it into place once complete")]
+  atomic: bool,
+  #[arg(long, help = "write JSON files (the manifest, sidecars, and --json-errors) on a single line instead of pretty-printed")]
+  compact: bool,
+  #[arg(
+    long,
+    value_name = "OCTAL",
+    value_parser = parse_mode,
+    conflicts_with = "dedupe_across",
+    help = "set permissions of extracted files (ignored on platforms other than Unix)"
+  )]
+  chmod: Option<u32>,
+  #[arg(long, help = "log entries that fail to extract and carry on with the rest (exiting with an error at the end)")]
+  continue_on_error: bool,
+  #[arg(long, value_name = "DIR", help = "store file contents by hash in DIR, hardlinking identical files instead of writing them again")]
+  dedupe_across: Option<PathBuf>,
+  #[arg(long, help = "append an extension detected from the content of files that have none")]
+  detect_extension: bool,
   #[arg(value_name = "ENTRY", help = "a list of filenames to extract from the archive")]
   entries: Vec<String>,
+  #[arg(long, value_name = "FILE", help = "read additional entries (one per line) from a file, or from stdin when FILE is -")]
+  entries_from: Option<PathBuf>,
+  #[arg(
+    long,
+    value_name = "EXPR",
+    help = "only extract entries matching an expression",
+    long_help = "only extract entries matching an expression, such as 'size > 1000 && tag == \"texture\"'. Expressions compare the fields index, name, size, and tag using ==, !=, <, <=, >, and >=, and combine comparisons with &&, ||, !, and parentheses."
+  )]
+  filter_expr: Option<Filter>,
+  #[arg(long, requires = "max_entries", help = "extract even if more entries are selected than --max-entries allows")]
+  force: bool,
+  #[arg(long, value_name = "FILE", requires = "continue_on_error", help = "write entries that failed to extract to a file as JSON")]
+  json_errors: Option<PathBuf>,
+  #[arg(
+    long,
+    value_name = "MODE",
+    conflicts_with_all = ["skip_zip_detection", "zip_automagic"],
+    help = "what to do with the main_xml entry (a clearer alternative to --zip-automagic)",
+    long_help = "what to do with the main_xml entry: [unzip] decompresses it in-place (same as --zip-automagic unpack), [keep] writes it as stored (same as --zip-automagic none), and [wrap] adds the missing ZIP Central Directory so it can be opened as a standalone zip (same as --zip-automagic fix)."
+  )]
+  main_xml: Option<MainXml>,
+  #[arg(long, value_name = "FILE", help = "write a manifest of extracted files (entry, tag, size, path, and SHA-256)")]
+  manifest: Option<PathBuf>,
+  #[arg(long, value_name = "FORMAT", default_value = "json", requires = "manifest", help = "format to write the manifest in")]
+  manifest_format: ManifestFormat,
+  #[arg(long, value_name = "N", help = "refuse to extract if more than N entries are selected")]
+  max_entries: Option<usize>,
+  #[arg(
+    long,
+    help = "lowercase file names, replace spaces with underscores, and drop characters other than ASCII letters, digits, '.', '-', and '_' as well as '..' components (the original name is kept in the manifest and sidecar)"
+  )]
+  normalize_names: bool,
+  #[arg(long, help = "prefix each extracted file with its zero-padded index in the archive")]
+  numbered: bool,
+  #[arg(
+    long,
+    value_name = "POLICY",
+    default_value = "fail",
+    help = "what to do when zipped content cannot be processed",
+    long_help = "what to do when zipped content cannot be processed: [fail] stops with an error, [raw] writes the entry as-is, and [skip] leaves the entry out (with a warning)."
+  )]
+  on_zip_error: ZipErrorPolicy,
+  #[arg(long, conflicts_with = "only_zip", help = "only extract entries whose content is not zipped")]
+  only_raw: bool,
+  #[arg(
+    long,
+    value_name = "MANIFEST",
+    conflicts_with = "tar",
+    help = "only extract entries whose content changed since an earlier extraction",
+    long_help = "only extract entries whose content changed since an earlier extraction, by comparing SHA-256 hashes against the JSON manifest it wrote with --manifest. Unchanged entries are skipped, but still recorded when writing a new manifest."
+  )]
+  only_new: Option<PathBuf>,
+  #[arg(long, help = "only extract entries whose content is zipped (typically main_xml)")]
+  only_zip: bool,
   #[arg(long, short, value_name = "DIR", default_value = "output", help = "a directory to extract into")]
   output: PathBuf,
+  #[arg(
+    long,
+    value_name = "LAYOUT",
+    default_value = "flat",
+    help = "how to arrange extracted files in the output directory",
+    long_help = "how to arrange extracted files in the output directory: [flat] puts everything in the output directory itself, [tag] groups files into a subdirectory per tag (untagged files stay at the top, and entries whose tag is not a plain directory name fail), and [type] groups files into a subdirectory per file type (detected from their content, or their extension)."
+  )]
+  output_layout: OutputLayout,
+  #[arg(long, value_name = "FILE", help = "write progress events to a file as newline-delimited JSON")]
+  progress_to: Option<PathBuf>,
+  #[arg(long, value_name = "FILE", help = "rename extracted files using a file of old=new lines")]
+  rename_map: Option<PathBuf>,
+  #[arg(long, help = "list the entries that were not selected for extraction on stderr once finished")]
+  report_unextracted: bool,
+  #[arg(long, help = "write a <file>.meta.json next to each extracted file with the entry's tag, sizes, compression, and SHA-256")]
+  sidecar: bool,
+  #[arg(
+    long,
+    conflicts_with = "zip_automagic",
+    help = "write every entry as raw bytes without checking for zipped content (same as --zip-automagic none)"
+  )]
+  skip_zip_detection: bool,
+  #[arg(long, help = "print a JSON summary of the extraction to stdout once finished")]
+  summary: bool,
   #[arg(long, help = "entries refer to tags rather than filenames")]
   tagged: bool,
+  #[arg(long, conflicts_with = "dedupe_across", help = "store each entry's tag in the user.orphist.tag extended attribute of its file (Unix only)")]
+  tags_as_xattr: bool,
+  #[arg(
+    long,
+    conflicts_with_all = ["atomic", "dedupe_across", "output", "sidecar", "summary", "tags_as_xattr"],
+    help = "write extracted files to stdout as a tar archive instead of into a directory",
+    long_help = "write extracted files to stdout as a tar archive instead of into a directory. If the command is aborted (for example by --timeout), the archive written so far is left truncated."
+  )]
+  tar: bool,
   #[arg(long, short, help = "verbose output")]
   verbose: bool,
   #[arg(
@@ -179,68 +598,1187 @@ enum ZipAutomagic {
 impl Extract {
   pub fn execute(&self, archive: Archive) -> anyhow::Result<()> {
     let Self {
+      chmod,
+      compact,
+      continue_on_error,
+      dedupe_across,
       entries,
+      entries_from,
+      filter_expr,
+      force,
+      json_errors,
+      manifest,
+      manifest_format,
+      max_entries,
+      only_new,
+      only_raw,
+      only_zip,
       output,
+      progress_to,
+      rename_map,
+      report_unextracted,
+      summary,
       tagged,
-      verbose,
-      zip_automagic,
+      tags_as_xattr,
+      tar,
+      ..
     } = self;
 
-    if !output.exists() {
+    let mut entries = entries.clone();
+    if let Some(path) = entries_from {
+      entries.extend(read_entries(path)?);
+    }
+
+    let renames = match rename_map {
+      Some(path) => read_rename_map(path)?,
+      None => HashMap::new(),
+    };
+
+    let previous = match only_new {
+      Some(path) => ManifestRecord::from_json(&std::fs::read_to_string(path)?)?
+        .into_iter()
+        .map(|record| (record.entry.clone(), record))
+        .collect(),
+      None => HashMap::new(),
+    };
+
+    if !*tar && !output.exists() {
       std::fs::create_dir_all(&output)?;
     }
 
-    for (metadata, data) in archive.body.metadata.into_iter().zip(archive.body.data) {
-      let qualifying_tag = *tagged && !metadata.tag.is_empty() && (entries.is_empty() || entries.contains(&metadata.tag));
-      let qualifying_file = !*tagged && (entries.is_empty() || entries.contains(&metadata.file_name));
-      if qualifying_tag || qualifying_file {
-        if metadata.tag == "main_xml" {
-          let path = match zip_automagic {
-            ZipAutomagic::None | ZipAutomagic::Fix | ZipAutomagic::Unpack => output.join(&metadata.file_name),
-            ZipAutomagic::Rename | ZipAutomagic::Rewrite if metadata.file_name.ends_with(".zip") => output.join(&metadata.file_name),
-            ZipAutomagic::Rename | ZipAutomagic::Rewrite => output.join(metadata.file_name.clone() + ".zip"),
-          };
+    if let Some(store) = dedupe_across {
+      if !store.exists() {
+        std::fs::create_dir_all(store)?;
+      }
+    }
 
-          if *verbose {
-            println!("extract: {} ({} bytes)", &metadata.file_name, &metadata.file_size);
-          }
+    if chmod.is_some() && cfg!(not(unix)) {
+      log::warn!("--chmod is only supported on Unix, permissions will not be changed");
+    }
 
-          let mut file = File::create(&path)?;
-
-          if *zip_automagic == ZipAutomagic::None {
-            file.write_all(&data)?;
-          } else {
-            let mut reader = Cursor::new(data);
-            let mut entry = synthzip::Entry::read(&mut reader)?;
-
-            if *zip_automagic == ZipAutomagic::Unpack {
-              let data = entry.decompress()?;
-              file.write_all(&data)?;
-            } else {
-              if *zip_automagic == ZipAutomagic::Rewrite {
-                entry.header.file_name = metadata.file_name;
-              }
-              let mut cd = synthzip::CentralDirectory::new();
-              cd.add(&entry)?;
-              entry.write(&mut file)?;
-              cd.write(&mut file)?;
-            }
-          }
+    if *tags_as_xattr && cfg!(not(unix)) {
+      log::warn!("--tags-as-xattr is only supported on Unix, tags will not be stored");
+    }
 
-          file.flush()?;
-        } else {
-          let path = output.join(&metadata.file_name);
-          if *verbose {
-            println!("extract: {} ({} bytes)", &metadata.file_name, &metadata.file_size);
-          }
+    let mut context = ExtractContext {
+      claimed: HashMap::new(),
+      previous,
+      renames,
+      width: archive.body.metadata.len().to_string().len().max(3),
+    };
+
+    let selected: Vec<bool> = archive
+      .body
+      .metadata
+      .iter()
+      .zip(&archive.body.data)
+      .enumerate()
+      .map(|(index, (metadata, data))| {
+        let fields = Fields {
+          index: index as u64,
+          name: &metadata.file_name,
+          size: metadata.file_size as u64,
+          tag: &metadata.tag,
+        };
+        let kind = match (*only_zip, *only_raw) {
+          (true, _) => is_zip(data),
+          (_, true) => !is_zip(data),
+          _ => true,
+        };
+        kind && selects(&entries, *tagged, filter_expr.as_ref(), &fields)
+      })
+      .collect();
+
+    let total = selected.iter().filter(|selected| **selected).count();
+
+    if let Some(max_entries) = max_entries {
+      if total > *max_entries && !*force {
+        anyhow::bail!("{total} entries selected for extraction, which is more than --max-entries {max_entries} (use --force to extract anyway)");
+      }
+    }
+
+    let mut progress = progress_to.as_deref().map(|path| Progress::create(path, total)).transpose()?;
+
+    let mut extracted = 0usize;
+    let mut skipped = 0usize;
+    let mut failed = 0usize;
+    let mut bytes_written = 0usize;
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    let mut unextracted = Vec::new();
+    let mut tar = tar.then(|| TarBuilder::new(std::io::stdout().lock()));
+
+    for (index, ((metadata, data), selected)) in archive.body.metadata.into_iter().zip(archive.body.data).zip(selected).enumerate() {
+      if !selected {
+        skipped += 1;
+        if *report_unextracted {
+          unextracted.push(metadata);
+        }
+        continue;
+      }
+
+      let fields = Fields {
+        index: index as u64,
+        name: &metadata.file_name,
+        size: metadata.file_size as u64,
+        tag: &metadata.tag,
+      };
+
+      let recorded = match self.extract_entry(&fields, data, &mut context, tar.as_mut()) {
+        Ok(Extracted::Skipped) => {
+          skipped += 1;
+          None
+        }
+        Ok(Extracted::Unchanged(path, data)) => {
+          skipped += 1;
+          Some((path, data))
+        }
+        Ok(Extracted::Written(path, data)) => {
+          extracted += 1;
+          bytes_written += data.len();
+          Some((path, data))
+        }
+        Err(error) if *continue_on_error => {
+          log::error!("failed to extract {:?}: {error:#}", fields.name);
+          failed += 1;
+          errors.push(serde_json::json!({ "entry": fields.name, "tag": fields.tag, "error": format!("{error:#}") }));
+          None
+        }
+        Err(error) => return Err(error),
+      };
 
-          let mut file = File::create(&path)?;
-          file.write_all(&data)?;
-          file.flush()?;
+      if let Some((path, data)) = recorded.filter(|_| manifest.is_some()) {
+        records.push(ManifestRecord {
+          entry: fields.name.to_string(),
+          tag: fields.tag.to_string(),
+          size: data.len(),
+          path,
+          sha256: sha256(&data),
+        });
+      }
+
+      if let Some(progress) = progress.as_mut() {
+        progress.advance(fields.name)?;
+      }
+    }
+
+    if let Some(tar) = tar {
+      tar.into_inner()?.flush()?;
+    }
+
+    if *report_unextracted {
+      eprintln!("{} of {} entries were not selected for extraction", unextracted.len(), total + unextracted.len());
+      for metadata in &unextracted {
+        match metadata.tag.as_str() {
+          "" => eprintln!("unextracted: {}", metadata.file_name),
+          tag => eprintln!("unextracted: {} ({tag})", metadata.file_name),
         }
       }
     }
 
+    if *summary {
+      let summary = serde_json::json!({ "extracted": extracted, "skipped": skipped, "failed": failed, "bytes_written": bytes_written });
+      // always a single line, so scripts can parse the last line of stdout
+      println!("{}", json::format(&summary, true));
+    }
+
+    if let Some(path) = manifest {
+      let contents = match manifest_format {
+        ManifestFormat::Csv => ManifestRecord::to_csv(&records),
+        ManifestFormat::Json => ManifestRecord::to_json(&records, *compact),
+      };
+      std::fs::write(path, contents)?;
+    }
+
+    if let Some(path) = json_errors {
+      std::fs::write(path, json::format(&serde_json::Value::Array(errors), *compact))?;
+    }
+
+    if failed > 0 {
+      anyhow::bail!("failed to extract {failed} of {total} entries");
+    }
+
     Ok(())
   }
+
+  fn extract_entry(&self, fields: &Fields, data: Vec<u8>, context: &mut ExtractContext, tar: Option<&mut TarBuilder>) -> anyhow::Result<Extracted> {
+    let Self {
+      atomic,
+      chmod,
+      compact,
+      dedupe_across,
+      detect_extension,
+      normalize_names,
+      numbered,
+      on_zip_error,
+      output,
+      output_layout,
+      sidecar,
+      tags_as_xattr,
+      verbose,
+      ..
+    } = self;
+
+    let zip_automagic = self.zip_automagic();
+    let stored_size = data.len();
+    let zipped = is_zip(&data);
+    let name = context.renames.get(fields.name).map_or(fields.name, String::as_str);
+    let normalized;
+    let name = if *normalize_names {
+      normalized = normalize_name(name);
+      normalized.as_str()
+    } else {
+      name
+    };
+
+    let (path, data, unpacked) = if fields.tag == "main_xml" {
+      let path = match zip_automagic {
+        ZipAutomagic::None | ZipAutomagic::Fix | ZipAutomagic::Unpack => PathBuf::from(name),
+        ZipAutomagic::Rename | ZipAutomagic::Rewrite if name.ends_with(".zip") => PathBuf::from(name),
+        ZipAutomagic::Rename | ZipAutomagic::Rewrite => PathBuf::from(format!("{name}.zip")),
+      };
+
+      let (data, unpacked) = if zip_automagic == ZipAutomagic::None {
+        (data, false)
+      } else {
+        match automagic(&data, zip_automagic, fields.name) {
+          Ok(processed) => (processed, zip_automagic == ZipAutomagic::Unpack),
+          Err(error) => match on_zip_error {
+            ZipErrorPolicy::Fail => return Err(error),
+            ZipErrorPolicy::Raw => {
+              log::warn!("failed to process zipped content of {:?} ({error:#}), writing it as-is", fields.name);
+              (data, false)
+            }
+            ZipErrorPolicy::Skip => {
+              log::warn!("failed to process zipped content of {:?} ({error:#}), skipping it", fields.name);
+              return Ok(Extracted::Skipped);
+            }
+          },
+        }
+      };
+
+      (path, data, unpacked)
+    } else {
+      (PathBuf::from(name), data, false)
+    };
+
+    let path = if *detect_extension { with_detected_extension(path, &data) } else { path };
+
+    let path = if *numbered {
+      let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+      path.with_file_name(format!("{:0width$}_{file_name}", fields.index, width = context.width))
+    } else {
+      path
+    };
+
+    let path = match output_layout {
+      OutputLayout::Flat => path,
+      OutputLayout::Tag if fields.tag.is_empty() => path,
+      OutputLayout::Tag => tag_directory(fields.tag)?.join(path),
+      OutputLayout::Type => Path::new(&file_type(&path, &data)).join(path),
+    };
+
+    if *normalize_names {
+      if let Some(other) = context.claimed.get(&path) {
+        anyhow::bail!("{other:?} and {:?} both normalize to {path:?} (use --numbered to keep them apart)", fields.name);
+      }
+      context.claimed.insert(path.clone(), fields.name.to_string());
+    }
+
+    if let Some(previous) = context.previous.get(fields.name) {
+      if previous.path == output.join(&path) && previous.path.exists() && previous.sha256 == sha256(&data) {
+        log::debug!("skipping {:?} because it has not changed since the previous extraction", fields.name);
+        return Ok(Extracted::Unchanged(previous.path.clone(), data));
+      }
+    }
+
+    if *verbose {
+      eprintln!("extract: {} ({} bytes)", fields.name, fields.size);
+    }
+
+    if let Some(tar) = tar {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(data.len() as u64);
+      header.set_mode(chmod.unwrap_or(0o644));
+      tar.append_data(&mut header, &path, data.as_slice())?;
+
+      return Ok(Extracted::Written(path, data));
+    }
+
+    let path = output.join(path);
+
+    if let Some(parent) = path.parent() {
+      if !parent.exists() {
+        std::fs::create_dir_all(parent)?;
+      }
+    }
+
+    match dedupe_across {
+      Some(store) => link_deduplicated(store, &path, &data, *atomic)?,
+      None => write_file(&path, &data, *atomic)?,
+    }
+
+    if let Some(mode) = chmod {
+      set_mode(&path, *mode)?;
+    }
+
+    if *tags_as_xattr && !fields.tag.is_empty() {
+      set_tag_attribute(&path, fields.tag)?;
+    }
+
+    if *sidecar {
+      let metadata = serde_json::json!({
+        "entry": fields.name,
+        "tag": fields.tag,
+        "declared_size": fields.size,
+        "stored_size": stored_size,
+        "zipped": zipped,
+        "unpacked": unpacked,
+        "size": data.len(),
+        "sha256": sha256(&data),
+      });
+
+      let mut sidecar = path.clone().into_os_string();
+      sidecar.push(".meta.json");
+      std::fs::write(sidecar, json::format(&metadata, *compact))?;
+    }
+
+    Ok(Extracted::Written(path, data))
+  }
+
+  fn zip_automagic(&self) -> ZipAutomagic {
+    match self.main_xml {
+      _ if self.skip_zip_detection => ZipAutomagic::None,
+      Some(MainXml::Keep) => ZipAutomagic::None,
+      Some(MainXml::Unzip) => ZipAutomagic::Unpack,
+      Some(MainXml::Wrap) => ZipAutomagic::Fix,
+      None => self.zip_automagic,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[clap(rename_all = "lowercase")]
+#[remain::sorted]
+enum MainXml {
+  Keep,
+  Unzip,
+  Wrap,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[clap(rename_all = "lowercase")]
+#[remain::sorted]
+enum ZipErrorPolicy {
+  #[default]
+  Fail,
+  Raw,
+  Skip,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[clap(rename_all = "lowercase")]
+#[remain::sorted]
+enum OutputLayout {
+  #[default]
+  Flat,
+  Tag,
+  Type,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[clap(rename_all = "lowercase")]
+#[remain::sorted]
+enum ManifestFormat {
+  Csv,
+  Json,
+}
+
+#[derive(Debug, Default)]
+struct ExtractContext {
+  claimed: HashMap<PathBuf, String>,
+  previous: HashMap<String, ManifestRecord>,
+  renames: HashMap<String, String>,
+  width: usize,
+}
+
+#[derive(Debug)]
+enum Extracted {
+  Skipped,
+  Unchanged(PathBuf, Vec<u8>),
+  Written(PathBuf, Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct ManifestRecord {
+  entry: String,
+  path: PathBuf,
+  sha256: String,
+  size: usize,
+  tag: String,
+}
+
+impl ManifestRecord {
+  fn to_json(records: &[Self], compact: bool) -> String {
+    let records: serde_json::Value = records
+      .iter()
+      .map(|record| serde_json::json!({ "entry": record.entry, "tag": record.tag, "size": record.size, "path": record.path.to_string_lossy(), "sha256": record.sha256 }))
+      .collect();
+    json::format(&records, compact)
+  }
+
+  fn from_json(contents: &str) -> anyhow::Result<Vec<Self>> {
+    let manifest: serde_json::Value = serde_json::from_str(contents)?;
+    let Some(records) = manifest.as_array() else {
+      anyhow::bail!("manifest is not a JSON array of records");
+    };
+
+    records
+      .iter()
+      .map(|record| {
+        match (
+          record["entry"].as_str(),
+          record["tag"].as_str(),
+          record["size"].as_u64(),
+          record["path"].as_str(),
+          record["sha256"].as_str(),
+        ) {
+          (Some(entry), Some(tag), Some(size), Some(path), Some(sha256)) => Ok(Self {
+            entry: entry.to_string(),
+            path: PathBuf::from(path),
+            sha256: sha256.to_string(),
+            size: size as usize,
+            tag: tag.to_string(),
+          }),
+          _ => anyhow::bail!("invalid record in manifest: {record}"),
+        }
+      })
+      .collect()
+  }
+
+  fn to_csv(records: &[Self]) -> String {
+    let mut contents = String::from("entry,tag,size,path,sha256\n");
+    for record in records {
+      let size = record.size.to_string();
+      let path = record.path.to_string_lossy();
+      contents.push_str(&csv::row([
+        record.entry.as_str(),
+        record.tag.as_str(),
+        size.as_str(),
+        &*path,
+        record.sha256.as_str(),
+      ]));
+      contents.push('\n');
+    }
+    contents
+  }
+}
+
+fn automagic(data: &[u8], zip_automagic: ZipAutomagic, file_name: &str) -> anyhow::Result<Vec<u8>> {
+  let mut reader = Cursor::new(data);
+  let mut entry = synthzip::Entry::read(&mut reader)?;
+
+  if zip_automagic == ZipAutomagic::Unpack {
+    return Ok(entry.decompress()?);
+  }
+
+  if zip_automagic == ZipAutomagic::Rewrite {
+    entry.header.file_name = file_name.to_string();
+  }
+
+  let mut cd = synthzip::CentralDirectory::new();
+  cd.add(&entry)?;
+  let mut data = Vec::new();
+  entry.write(&mut data)?;
+  cd.write(&mut data)?;
+  Ok(data)
+}
+
+type TarBuilder = tar::Builder<std::io::StdoutLock<'static>>;
+
+fn selects(entries: &[String], tagged: bool, filter: Option<&Filter>, fields: &Fields) -> bool {
+  let listed = if tagged {
+    !fields.tag.is_empty() && (entries.is_empty() || entries.iter().any(|entry| entry == fields.tag))
+  } else {
+    entries.is_empty() || entries.iter().any(|entry| entry == fields.name)
+  };
+
+  listed && filter.is_none_or(|filter| filter.matches(fields))
+}
+
+fn write_file(path: &Path, data: &[u8], atomic: bool) -> anyhow::Result<()> {
+  if !atomic {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.flush()?;
+    return Ok(());
+  }
+
+  let partial = partial_path(path);
+  let written = PARTIAL_FILES.create(&partial, |partial| File::create(partial)).and_then(|mut file| {
+    file.write_all(data)?;
+    file.flush()?;
+    file.sync_all()
+  });
+
+  match written {
+    Ok(()) => PARTIAL_FILES.rename(&partial, path)?,
+    Err(error) => {
+      PARTIAL_FILES.remove(&partial);
+      Err(error)?
+    }
+  }
+
+  Ok(())
+}
+
+fn link_deduplicated(store: &Path, path: &Path, data: &[u8], atomic: bool) -> anyhow::Result<()> {
+  let stored = store.join(sha256(data));
+  if !stored.exists() {
+    write_file(&stored, data, atomic)?;
+  }
+
+  let target = if atomic { partial_path(path) } else { path.to_owned() };
+  if target.exists() {
+    std::fs::remove_file(&target)?;
+  }
+
+  let link = |target: &Path| {
+    std::fs::hard_link(&stored, target).or_else(|error| {
+      log::debug!("failed to hardlink {path:?} to {stored:?} ({error}), copying instead");
+      std::fs::copy(&stored, target).map(|_| ())
+    })
+  };
+  let linked = if atomic { PARTIAL_FILES.create(&target, link) } else { link(&target) };
+
+  match linked {
+    Ok(()) if atomic => PARTIAL_FILES.rename(&target, path)?,
+    Ok(()) => {}
+    Err(error) => {
+      if atomic {
+        PARTIAL_FILES.remove(&target);
+      }
+      Err(error)?
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+  use std::os::unix::fs::PermissionsExt;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> anyhow::Result<()> {
+  Ok(())
+}
+
+#[cfg(unix)]
+fn set_tag_attribute(path: &Path, tag: &str) -> anyhow::Result<()> {
+  xattr::set(path, "user.orphist.tag", tag.as_bytes())?;
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_tag_attribute(_path: &Path, _tag: &str) -> anyhow::Result<()> {
+  Ok(())
+}
+
+fn parse_key(key: &str) -> Result<u32, std::num::ParseIntError> {
+  match key.get(..2) {
+    Some("0x" | "0X") => u32::from_str_radix(&key[2..], 16),
+    Some("0b" | "0B") => u32::from_str_radix(&key[2..], 2),
+    _ => key.parse(),
+  }
+}
+
+fn parse_mode(value: &str) -> Result<u32, std::num::ParseIntError> {
+  u32::from_str_radix(value.trim_start_matches("0o"), 8)
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+  let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+  path.with_file_name(format!(".{file_name}.partial"))
+}
+
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+fn is_zip(data: &[u8]) -> bool {
+  data.starts_with(ZIP_SIGNATURE)
+}
+
+const ZIP_LOCAL_HEADER_SIZE: usize = 30;
+const ZIP_METHOD_STORED: u16 = 0;
+const ZIP_FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ZipPayload {
+  compressed_size: usize,
+  declared_uncompressed_size: Option<usize>,
+  method: u16,
+}
+
+impl ZipPayload {
+  fn uncompressed_size(&self, data: &[u8]) -> Option<usize> {
+    // only entries written with a data descriptor need decompressing to find out
+    self.declared_uncompressed_size.or_else(|| unzip(data).map(|unpacked| unpacked.len()))
+  }
+}
+
+fn zip_payload(data: &[u8]) -> Option<ZipPayload> {
+  if !is_zip(data) || data.len() < ZIP_LOCAL_HEADER_SIZE {
+    return None;
+  }
+
+  let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+  let u32_at = |offset: usize| {
+    u32::from_le_bytes([
+      data[offset],
+      data[offset + 1],
+      data[offset + 2],
+      data[offset + 3],
+    ])
+  };
+
+  let method = u16_at(8);
+  let available = data.len().checked_sub(ZIP_LOCAL_HEADER_SIZE + usize::from(u16_at(26)) + usize::from(u16_at(28)))?;
+
+  // entries written with a data descriptor leave the sizes in the local header empty, so fall back to everything after the header
+  let payload = if u16_at(6) & ZIP_FLAG_DATA_DESCRIPTOR != 0 {
+    ZipPayload {
+      compressed_size: available,
+      declared_uncompressed_size: None,
+      method,
+    }
+  } else {
+    ZipPayload {
+      compressed_size: (u32_at(18) as usize).min(available),
+      declared_uncompressed_size: Some(u32_at(22) as usize),
+      method,
+    }
+  };
+
+  Some(payload)
+}
+
+fn normalize_name(name: &str) -> String {
+  let components: Vec<String> = name
+    .split('/')
+    .map(|component| {
+      let normalized: String = component
+        .chars()
+        .filter_map(|c| match c {
+          ' ' => Some('_'),
+          c if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') => Some(c.to_ascii_lowercase()),
+          _ => None,
+        })
+        .collect();
+      // a stem made entirely of stripped characters would otherwise leave a hidden file behind
+      if normalized.starts_with('.') && !component.starts_with('.') {
+        format!("_{normalized}")
+      } else {
+        normalized
+      }
+    })
+    .filter(|component: &String| !component.trim_matches('.').is_empty())
+    .collect();
+
+  if components.is_empty() {
+    "_".to_string()
+  } else {
+    components.join("/")
+  }
+}
+
+fn sniff_extension(data: &[u8]) -> Option<&'static str> {
+  let text = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data).trim_ascii_start();
+
+  if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+    Some("png")
+  } else if data.starts_with(b"\xFF\xD8\xFF") {
+    Some("jpg")
+  } else if data.starts_with(b"MOC3") {
+    Some("moc3")
+  } else if is_zip(data) {
+    Some("zip")
+  } else if text.starts_with(b"<?xml") {
+    Some("xml")
+  } else if text.starts_with(b"{") || text.starts_with(b"[") {
+    Some("json")
+  } else {
+    None
+  }
+}
+
+fn file_type(path: &Path, data: &[u8]) -> String {
+  sniff_extension(data)
+    .map(String::from)
+    .or_else(|| path.extension().map(|extension| extension.to_string_lossy().to_lowercase()))
+    .unwrap_or_else(|| String::from("other"))
+}
+
+fn tag_directory(tag: &str) -> anyhow::Result<&Path> {
+  let path = Path::new(tag);
+  match path.components().collect::<Vec<_>>().as_slice() {
+    [Component::Normal(_)] if !tag.contains(['/', '\\']) => Ok(path),
+    _ => anyhow::bail!("tag {tag:?} cannot be used as a directory name"),
+  }
+}
+
+fn with_detected_extension(path: PathBuf, data: &[u8]) -> PathBuf {
+  match sniff_extension(data) {
+    Some(extension) if path.extension().is_none() => {
+      let mut path = path.into_os_string();
+      path.push(".");
+      path.push(extension);
+      path.into()
+    }
+    _ => path,
+  }
+}
+
+fn read_entries(path: &Path) -> anyhow::Result<Vec<String>> {
+  let contents = if path == Path::new("-") {
+    let mut contents = String::new();
+    std::io::stdin().read_to_string(&mut contents)?;
+    contents
+  } else {
+    std::fs::read_to_string(path)?
+  };
+
+  Ok(contents.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
+fn read_rename_map(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+  let contents = std::fs::read_to_string(path)?;
+  let mut renames = HashMap::new();
+
+  for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+    let Some((old, new)) = line.split_once('=') else {
+      anyhow::bail!("invalid line in rename map (expected old=new): {line:?}");
+    };
+    renames.insert(old.trim().to_string(), new.trim().to_string());
+  }
+
+  Ok(renames)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::Parser;
+
+  fn extract_into(directory: &Path, arguments: &[&str], entries: &[(&str, &str, &[u8])]) -> anyhow::Result<Vec<Extracted>> {
+    let output = directory.to_string_lossy().to_string();
+    let extract = Extract::try_parse_from(["extract", "--output", output.as_str()].iter().chain(arguments).copied())?;
+    let mut context = ExtractContext { width: 3, ..Default::default() };
+    if let Some(path) = &extract.only_new {
+      context.previous = ManifestRecord::from_json(&std::fs::read_to_string(path)?)?
+        .into_iter()
+        .map(|record| (record.entry.clone(), record))
+        .collect();
+    }
+
+    entries
+      .iter()
+      .enumerate()
+      .map(|(index, (name, tag, data))| {
+        let fields = Fields {
+          index: index as u64,
+          name,
+          size: data.len() as u64,
+          tag,
+        };
+        extract.extract_entry(&fields, data.to_vec(), &mut context, None)
+      })
+      .collect()
+  }
+
+  fn extract_all(arguments: &[&str], entries: &[(&str, &str, &[u8])]) -> anyhow::Result<Vec<PathBuf>> {
+    let directory = tempfile::tempdir()?;
+    let mut paths = Vec::new();
+
+    for extracted in extract_into(directory.path(), arguments, entries)? {
+      if let Extracted::Written(path, _) = extracted {
+        paths.push(path.strip_prefix(directory.path())?.to_path_buf());
+      }
+    }
+
+    Ok(paths)
+  }
+
+  fn local_entry(method: u16, payload: &[u8], unpacked_size: u32) -> Vec<u8> {
+    local_entry_with_flags(0, method, payload, unpacked_size)
+  }
+
+  fn local_entry_with_flags(flags: u16, method: u16, payload: &[u8], unpacked_size: u32) -> Vec<u8> {
+    let mut data = ZIP_SIGNATURE.to_vec();
+    data.extend(20u16.to_le_bytes());
+    data.extend(flags.to_le_bytes());
+    data.extend(method.to_le_bytes());
+    data.extend([0; 8]);
+    data.extend((payload.len() as u32).to_le_bytes());
+    data.extend(unpacked_size.to_le_bytes());
+    data.extend(5u16.to_le_bytes());
+    data.extend(0u16.to_le_bytes());
+    data.extend(b"a.xml");
+    data.extend(payload);
+    data
+  }
+
+  #[test]
+  fn stored_entries_have_a_ratio_of_one() {
+    let payload = b"<xml>stored</xml>";
+    let data = local_entry(ZIP_METHOD_STORED, payload, payload.len() as u32);
+
+    assert_eq!(compression_ratio(&data), 1.0);
+  }
+
+  #[test]
+  fn deflated_entries_compare_the_header_sizes() {
+    let data = local_entry(8, &[0; 10], 100);
+
+    assert_eq!(zip_payload(&data).and_then(|payload| payload.uncompressed_size(&data)), Some(100));
+    assert_eq!(compression_ratio(&data), 0.1);
+  }
+
+  #[test]
+  fn data_descriptor_entries_do_not_trust_the_header_sizes() {
+    let data = local_entry_with_flags(ZIP_FLAG_DATA_DESCRIPTOR, 8, &[0; 10], 100);
+    let payload = zip_payload(&data).unwrap();
+
+    assert_eq!(payload.compressed_size, 10);
+    assert_eq!(payload.declared_uncompressed_size, None);
+  }
+
+  #[test]
+  fn raw_entries_have_a_ratio_of_one() {
+    assert_eq!(compression_ratio(b"\x89PNG\r\n\x1a\n"), 1.0);
+  }
+
+  #[test]
+  fn parses_keys_in_every_printed_format() {
+    assert_eq!(parse_key("3735928559"), Ok(0xDEAD_BEEF));
+    assert_eq!(parse_key("0xDEADBEEF"), Ok(0xDEAD_BEEF));
+    assert_eq!(parse_key("0Xdeadbeef"), Ok(0xDEAD_BEEF));
+    assert_eq!(parse_key("0b101"), Ok(5));
+    assert!(parse_key("0x").is_err());
+    assert!(parse_key("0x100000000").is_err());
+    assert!(parse_key("key").is_err());
+  }
+
+  #[test]
+  fn normalizes_names_into_portable_paths() {
+    assert_eq!(normalize_name("Texture 00.PNG"), "texture_00.png");
+    assert_eq!(normalize_name("Motions/Idle (loop).motion3.json"), "motions/idle_loop.motion3.json");
+    assert_eq!(normalize_name("../../etc/passwd"), "etc/passwd");
+    assert_eq!(normalize_name("/a//./b"), "a/b");
+    assert_eq!(normalize_name("??/.."), "_");
+    assert_eq!(normalize_name("日本語.png"), "_.png");
+    assert_eq!(normalize_name(".hidden"), ".hidden");
+  }
+
+  #[test]
+  fn normalized_names_collide_on_the_final_path() {
+    let error = extract_all(&["--normalize-names"], &[("Texture.PNG", "", b"png"), ("texture.png", "", b"png")]).unwrap_err();
+    assert!(error.to_string().contains("both normalize to"));
+
+    let error = extract_all(&["--normalize-names", "--detect-extension"], &[("Model", "", b"{}"), ("model.json", "", b"{}")]).unwrap_err();
+    assert!(error.to_string().contains("both normalize to"));
+
+    let paths = extract_all(&["--normalize-names", "--output-layout", "tag"], &[("Texture.PNG", "a", b"png"), ("texture.png", "b", b"png")]).unwrap();
+    assert_eq!(paths, [Path::new("a/texture.png"), Path::new("b/texture.png")]);
+
+    let paths = extract_all(&["--normalize-names", "--numbered"], &[("Texture.PNG", "", b"png"), ("texture.png", "", b"png")]).unwrap();
+    assert_eq!(paths, [Path::new("000_texture.png"), Path::new("001_texture.png")]);
+  }
+
+  #[test]
+  fn verify_reports_every_entry() {
+    let manifest = format!("{}  same\n{}  changed\n{}  removed\n", sha256(b"same"), sha256(b"before"), sha256(b"removed"));
+    let entries: [(&str, &[u8]); 3] = [
+      ("same", b"same"),
+      ("changed", b"after"),
+      ("added", b"added"),
+    ];
+
+    let statuses = Checksum::verify(entries, &manifest).unwrap();
+
+    assert_eq!(
+      statuses,
+      [
+        ("same".to_string(), ChecksumStatus::Ok),
+        ("changed".to_string(), ChecksumStatus::Failed),
+        ("added".to_string(), ChecksumStatus::New),
+        ("removed".to_string(), ChecksumStatus::Missing),
+      ]
+    );
+    assert_eq!(ChecksumStatus::Missing.to_string(), "MISSING");
+  }
+
+  #[test]
+  fn verify_rejects_malformed_lines() {
+    assert!(Checksum::verify(std::iter::empty(), "not a checksum").is_err());
+  }
+
+  #[test]
+  fn tree_groups_entries_under_shared_directories() {
+    let mut root = Tree::default();
+    root.insert("a/b.png", "10".to_string());
+    root.insert("a/c.png", "20".to_string());
+    root.insert("d.json", "30".to_string());
+
+    assert_eq!(root.render(), "a/\n  b.png (10)\n  c.png (20)\nd.json (30)\n");
+  }
+
+  fn manifest_records() -> Vec<ManifestRecord> {
+    vec![
+      ManifestRecord {
+        entry: "texture_00.png".to_string(),
+        path: PathBuf::from("output/texture_00.png"),
+        sha256: sha256(b"png"),
+        size: 3,
+        tag: "texture".to_string(),
+      },
+      ManifestRecord {
+        entry: "model, final.moc3".to_string(),
+        path: PathBuf::from("output/model, final.moc3"),
+        sha256: sha256(b"moc3"),
+        size: 4,
+        tag: String::new(),
+      },
+    ]
+  }
+
+  #[test]
+  fn manifest_csv_has_a_header_and_a_row_per_entry() {
+    let csv = ManifestRecord::to_csv(&manifest_records());
+    let lines: Vec<_> = csv.lines().collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "entry,tag,size,path,sha256");
+    assert_eq!(lines[1], format!("texture_00.png,texture,3,output/texture_00.png,{}", sha256(b"png")));
+    assert_eq!(lines[2], format!("\"model, final.moc3\",,4,\"output/model, final.moc3\",{}", sha256(b"moc3")));
+  }
+
+  #[test]
+  fn manifest_json_has_an_object_per_entry() {
+    let json: serde_json::Value = serde_json::from_str(&ManifestRecord::to_json(&manifest_records(), false)).unwrap();
+
+    assert_eq!(
+      json,
+      serde_json::json!([
+        { "entry": "texture_00.png", "tag": "texture", "size": 3, "path": "output/texture_00.png", "sha256": sha256(b"png") },
+        { "entry": "model, final.moc3", "tag": "", "size": 4, "path": "output/model, final.moc3", "sha256": sha256(b"moc3") },
+      ])
+    );
+  }
+
+  #[test]
+  fn entries_file_selects_exactly_the_listed_entries() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "main.xml\n  texture_00.png  \n\ntexture_02.png").unwrap();
+
+    let entries = read_entries(file.path()).unwrap();
+    assert_eq!(entries, ["main.xml", "texture_00.png", "texture_02.png"]);
+
+    let names = [
+      "main.xml",
+      "texture_00.png",
+      "texture_01.png",
+      "texture_02.png",
+      "model.moc3",
+    ];
+    let selected: Vec<_> = names
+      .iter()
+      .enumerate()
+      .filter(|(index, name)| {
+        let fields = Fields {
+          index: *index as u64,
+          name,
+          size: 0,
+          tag: "",
+        };
+        selects(&entries, false, None, &fields)
+      })
+      .map(|(_, name)| *name)
+      .collect();
+    assert_eq!(selected, ["main.xml", "texture_00.png", "texture_02.png"]);
+  }
+
+  #[test]
+  fn rename_map_maps_listed_names_only() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "texture_00.png = body.png\n\nmain.xml=model.xml").unwrap();
+
+    let renames = read_rename_map(file.path()).unwrap();
+
+    assert_eq!(renames.get("texture_00.png").map(String::as_str), Some("body.png"));
+    assert_eq!(renames.get("main.xml").map(String::as_str), Some("model.xml"));
+    assert_eq!(renames.get("texture_01.png"), None);
+  }
+
+  #[test]
+  fn rename_map_rejects_lines_without_a_separator() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "texture_00.png = body.png\ntexture_01.png").unwrap();
+
+    let error = read_rename_map(file.path()).unwrap_err();
+
+    assert!(error.to_string().contains("\"texture_01.png\""), "{error}");
+  }
+
+  #[test]
+  fn atomic_writes_leave_no_partial_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("texture_00.png");
+    std::fs::write(&path, b"old").unwrap();
+
+    write_file(&path, b"new", true).unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"new");
+    assert!(!partial_path(&path).exists());
+    assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn deduplicated_entries_share_an_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let directory = tempfile::tempdir().unwrap();
+    let store = directory.path().join("store");
+    std::fs::create_dir(&store).unwrap();
+
+    for atomic in [false, true] {
+      let first = directory.path().join(format!("first-{atomic}.png"));
+      let second = directory.path().join(format!("second-{atomic}.png"));
+      link_deduplicated(&store, &first, b"png", atomic).unwrap();
+      link_deduplicated(&store, &second, b"png", atomic).unwrap();
+
+      let inode = |path: &Path| std::fs::metadata(path).unwrap().ino();
+      assert_eq!(inode(&first), inode(&second));
+      assert_eq!(inode(&first), inode(&store.join(sha256(b"png"))));
+      assert_eq!(std::fs::read(&second).unwrap(), b"png");
+    }
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn set_mode_applies_permission_bits() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file = tempfile::NamedTempFile::new().unwrap();
+
+    for mode in [0o600, 0o644, 0o755] {
+      set_mode(file.path(), mode).unwrap();
+      assert_eq!(std::fs::metadata(file.path()).unwrap().permissions().mode() & 0o777, mode);
+    }
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn tag_attribute_is_stored_on_the_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("texture_00.png");
+    std::fs::write(&path, b"png").unwrap();
+
+    match set_tag_attribute(&path, "texture") {
+      Ok(()) => assert_eq!(xattr::get(&path, "user.orphist.tag").unwrap(), Some(b"texture".to_vec())),
+      // not every filesystem a temporary directory lives on supports user attributes
+      Err(error) if error.downcast_ref::<std::io::Error>().is_some_and(|error| error.kind() == std::io::ErrorKind::Unsupported) => {}
+      Err(error) => panic!("{error:#}"),
+    }
+  }
+
+  #[test]
+  fn only_new_skips_entries_unchanged_since_the_manifest() {
+    let directory = tempfile::tempdir().unwrap();
+    let entries: [(&str, &str, &[u8]); 2] = [
+      ("texture_00.png", "texture", b"png"),
+      ("model.moc3", "", b"MOC3 v1"),
+    ];
+
+    let records: Vec<_> = extract_into(directory.path(), &[], &entries)
+      .unwrap()
+      .into_iter()
+      .zip(entries)
+      .map(|(extracted, (entry, tag, _))| match extracted {
+        Extracted::Written(path, data) => ManifestRecord {
+          entry: entry.to_string(),
+          path,
+          sha256: sha256(&data),
+          size: data.len(),
+          tag: tag.to_string(),
+        },
+        extracted => panic!("expected {entry:?} to be written, got {extracted:?}"),
+      })
+      .collect();
+    let manifest = directory.path().join("manifest.json");
+    std::fs::write(&manifest, ManifestRecord::to_json(&records, false)).unwrap();
+    let manifest = manifest.to_string_lossy().to_string();
+
+    // a marker that is only overwritten if the entry is written again
+    let texture = directory.path().join("texture_00.png");
+    std::fs::write(&texture, b"untouched").unwrap();
+
+    let outcomes = extract_into(directory.path(), &["--only-new", manifest.as_str()], &entries).unwrap();
+    assert!(outcomes.iter().all(|outcome| matches!(outcome, Extracted::Unchanged(..))), "{outcomes:?}");
+    assert_eq!(std::fs::read(&texture).unwrap(), b"untouched");
+
+    let changed: [(&str, &str, &[u8]); 2] = [entries[0], ("model.moc3", "", b"MOC3 v2")];
+    let outcomes = extract_into(directory.path(), &["--only-new", manifest.as_str()], &changed).unwrap();
+    assert!(matches!(outcomes[0], Extracted::Unchanged(..)), "{outcomes:?}");
+    assert!(matches!(outcomes[1], Extracted::Written(..)), "{outcomes:?}");
+    assert_eq!(std::fs::read(directory.path().join("model.moc3")).unwrap(), b"MOC3 v2");
+  }
+
+  #[test]
+  fn manifest_json_round_trips() {
+    let records = manifest_records();
+    let parsed = ManifestRecord::from_json(&ManifestRecord::to_json(&records, true)).unwrap();
+
+    assert_eq!(parsed.len(), records.len());
+    assert_eq!(parsed[1].entry, records[1].entry);
+    assert_eq!(parsed[1].path, records[1].path);
+    assert_eq!(parsed[1].sha256, records[1].sha256);
+    assert!(ManifestRecord::from_json(r#"[{ "entry": "main.xml" }]"#).is_err());
+  }
+
+  #[test]
+  fn tags_must_be_a_single_directory_name() {
+    assert_eq!(tag_directory("texture").unwrap(), Path::new("texture"));
+    assert_eq!(tag_directory("main_xml").unwrap(), Path::new("main_xml"));
+    assert!(tag_directory("../..").is_err());
+    assert!(tag_directory("..").is_err());
+    assert!(tag_directory(".").is_err());
+    assert!(tag_directory("/tmp").is_err());
+    assert!(tag_directory("a/b").is_err());
+    assert!(tag_directory("a\\b").is_err());
+    assert!(tag_directory("texture/").is_err());
+  }
+
+  #[test]
+  fn sniffs_extensions_from_content() {
+    assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n...."), Some("png"));
+    assert_eq!(sniff_extension(b"\xFF\xD8\xFF\xE0"), Some("jpg"));
+    assert_eq!(sniff_extension(b"MOC3\x04"), Some("moc3"));
+    assert_eq!(sniff_extension(&local_entry(ZIP_METHOD_STORED, b"", 0)), Some("zip"));
+    assert_eq!(sniff_extension(b"\xEF\xBB\xBF  <?xml version=\"1.0\"?>"), Some("xml"));
+    assert_eq!(sniff_extension(b"\n{\"Version\": 3}"), Some("json"));
+    assert_eq!(sniff_extension(b"plain text"), None);
+  }
+
+  #[test]
+  fn detected_extensions_only_apply_to_names_without_one() {
+    let png = b"\x89PNG\r\n\x1a\n....";
+
+    assert_eq!(with_detected_extension(PathBuf::from("texture_00"), png), Path::new("texture_00.png"));
+    assert_eq!(with_detected_extension(PathBuf::from("notes.txt"), b"[1, 2, 3]"), Path::new("notes.txt"));
+    assert_eq!(with_detected_extension(PathBuf::from("v1.2"), png), Path::new("v1.2"));
+  }
 }
@@ -0,0 +1,285 @@
+use anyhow::bail;
+use std::{iter::Peekable, str::FromStr, vec::IntoIter};
+
+#[derive(Debug, Clone)]
+#[remain::sorted]
+pub enum Filter {
+  And(Box<Filter>, Box<Filter>),
+  Compare(Field, Comparison, Value),
+  Not(Box<Filter>),
+  Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[remain::sorted]
+pub enum Field {
+  Index,
+  Name,
+  Size,
+  Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[remain::sorted]
+pub enum Comparison {
+  Eq,
+  Ge,
+  Gt,
+  Le,
+  Lt,
+  Ne,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[remain::sorted]
+pub enum Value {
+  Number(u64),
+  Text(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fields<'a> {
+  pub index: u64,
+  pub name: &'a str,
+  pub size: u64,
+  pub tag: &'a str,
+}
+
+impl Filter {
+  pub fn matches(&self, fields: &Fields) -> bool {
+    match self {
+      Self::And(left, right) => left.matches(fields) && right.matches(fields),
+      Self::Compare(field, comparison, value) => match (field, value) {
+        (Field::Index, Value::Number(number)) => comparison.compare(&fields.index, number),
+        (Field::Name, Value::Text(text)) => comparison.compare(fields.name, text.as_str()),
+        (Field::Size, Value::Number(number)) => comparison.compare(&fields.size, number),
+        (Field::Tag, Value::Text(text)) => comparison.compare(fields.tag, text.as_str()),
+        _ => false,
+      },
+      Self::Not(filter) => !filter.matches(fields),
+      Self::Or(left, right) => left.matches(fields) || right.matches(fields),
+    }
+  }
+}
+
+impl Comparison {
+  fn compare<T: Ord + ?Sized>(&self, left: &T, right: &T) -> bool {
+    match self {
+      Self::Eq => left == right,
+      Self::Ge => left >= right,
+      Self::Gt => left > right,
+      Self::Le => left <= right,
+      Self::Lt => left < right,
+      Self::Ne => left != right,
+    }
+  }
+}
+
+impl FromStr for Filter {
+  type Err = anyhow::Error;
+
+  fn from_str(input: &str) -> anyhow::Result<Self> {
+    let mut tokens = tokenize(input)?.into_iter().peekable();
+    let filter = parse_or(&mut tokens)?;
+
+    match tokens.next() {
+      None => Ok(filter),
+      Some(token) => bail!("unexpected {token:?} after end of expression"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+  And,
+  Close,
+  Compare(Comparison),
+  Identifier(String),
+  Not,
+  Number(u64),
+  Open,
+  Or,
+  Text(String),
+}
+
+type Tokens = Peekable<IntoIter<Token>>;
+
+fn tokenize(input: &str) -> anyhow::Result<Vec<Token>> {
+  let mut tokens = Vec::new();
+  let mut chars = input.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    let token = match c {
+      c if c.is_whitespace() => continue,
+      '(' => Token::Open,
+      ')' => Token::Close,
+      '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+      '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+      '=' if chars.next_if_eq(&'=').is_some() => Token::Compare(Comparison::Eq),
+      '!' if chars.next_if_eq(&'=').is_some() => Token::Compare(Comparison::Ne),
+      '!' => Token::Not,
+      '<' if chars.next_if_eq(&'=').is_some() => Token::Compare(Comparison::Le),
+      '<' => Token::Compare(Comparison::Lt),
+      '>' if chars.next_if_eq(&'=').is_some() => Token::Compare(Comparison::Ge),
+      '>' => Token::Compare(Comparison::Gt),
+      '"' | '\'' => {
+        let mut text = String::new();
+        loop {
+          match chars.next() {
+            Some(close) if close == c => break,
+            Some('\\') => text.extend(chars.next()),
+            Some(other) => text.push(other),
+            None => bail!("unterminated string in expression"),
+          }
+        }
+        Token::Text(text)
+      }
+      c if c.is_ascii_digit() => {
+        let mut digits = String::from(c);
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+          digits.push(digit);
+        }
+        Token::Number(digits.parse()?)
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let mut identifier = String::from(c);
+        while let Some(next) = chars.next_if(|next| next.is_alphanumeric() || *next == '_') {
+          identifier.push(next);
+        }
+        Token::Identifier(identifier)
+      }
+      other => bail!("unexpected character {other:?} in expression"),
+    };
+
+    tokens.push(token);
+  }
+
+  Ok(tokens)
+}
+
+fn parse_or(tokens: &mut Tokens) -> anyhow::Result<Filter> {
+  let mut filter = parse_and(tokens)?;
+  while tokens.next_if_eq(&Token::Or).is_some() {
+    filter = Filter::Or(Box::new(filter), Box::new(parse_and(tokens)?));
+  }
+  Ok(filter)
+}
+
+fn parse_and(tokens: &mut Tokens) -> anyhow::Result<Filter> {
+  let mut filter = parse_unary(tokens)?;
+  while tokens.next_if_eq(&Token::And).is_some() {
+    filter = Filter::And(Box::new(filter), Box::new(parse_unary(tokens)?));
+  }
+  Ok(filter)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> anyhow::Result<Filter> {
+  match tokens.next() {
+    Some(Token::Not) => Ok(Filter::Not(Box::new(parse_unary(tokens)?))),
+    Some(Token::Open) => {
+      let filter = parse_or(tokens)?;
+      match tokens.next() {
+        Some(Token::Close) => Ok(filter),
+        other => bail!("expected ) but found {other:?}"),
+      }
+    }
+    Some(Token::Identifier(identifier)) => {
+      let field = match identifier.as_str() {
+        "index" => Field::Index,
+        "name" => Field::Name,
+        "size" => Field::Size,
+        "tag" => Field::Tag,
+        _ => bail!("unknown field {identifier:?} (expected one of index, name, size, tag)"),
+      };
+
+      let comparison = match tokens.next() {
+        Some(Token::Compare(comparison)) => comparison,
+        other => bail!("expected a comparison after {identifier:?} but found {other:?}"),
+      };
+
+      let value = match tokens.next() {
+        Some(Token::Number(number)) => Value::Number(number),
+        Some(Token::Text(text)) => Value::Text(text),
+        other => bail!("expected a number or string after {identifier:?} but found {other:?}"),
+      };
+
+      let comparable = matches!((field, &value), (Field::Index | Field::Size, Value::Number(_)) | (Field::Name | Field::Tag, Value::Text(_)));
+      if !comparable {
+        bail!("{identifier:?} cannot be compared with {value:?}");
+      }
+
+      Ok(Filter::Compare(field, comparison, value))
+    }
+    other => bail!("expected a field, ! or ( but found {other:?}"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ENTRIES: [Fields<'static>; 3] = [
+    Fields {
+      index: 0,
+      name: "main.xml",
+      size: 4096,
+      tag: "main_xml",
+    },
+    Fields {
+      index: 1,
+      name: "texture_00.png",
+      size: 2048,
+      tag: "texture",
+    },
+    Fields {
+      index: 2,
+      name: "it's \"quoted\".png",
+      size: 512,
+      tag: "",
+    },
+  ];
+
+  fn matching(expression: &str) -> Vec<u64> {
+    let filter: Filter = expression.parse().unwrap();
+    ENTRIES.iter().filter(|fields| filter.matches(fields)).map(|fields| fields.index).collect()
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or() {
+    assert_eq!(matching("index == 2 || size > 1000 && tag == 'texture'"), [1, 2]);
+    assert_eq!(matching("(index == 2 || size > 1000) && tag == 'texture'"), [1]);
+  }
+
+  #[test]
+  fn not_negates_the_next_term() {
+    assert_eq!(matching("!tag == 'texture'"), [0, 2]);
+    assert_eq!(matching("!(size >= 2048 || index == 2)"), Vec::<u64>::new());
+  }
+
+  #[test]
+  fn compares_numbers_and_text() {
+    assert_eq!(matching("size < 2048"), [2]);
+    assert_eq!(matching("size <= 2048 && index != 2"), [1]);
+    assert_eq!(matching("name == \"main.xml\""), [0]);
+  }
+
+  #[test]
+  fn strings_support_escapes() {
+    assert_eq!(matching(r#"name == "it's \"quoted\".png""#), [2]);
+    assert_eq!(matching(r#"name == 'it\'s \"quoted\".png'"#), [2]);
+  }
+
+  #[test]
+  fn rejects_mismatched_types() {
+    assert!("size == 'large'".parse::<Filter>().is_err());
+    assert!("tag > 3".parse::<Filter>().is_err());
+  }
+
+  #[test]
+  fn rejects_unknown_fields_and_malformed_expressions() {
+    assert!("colour == 'red'".parse::<Filter>().is_err());
+    assert!("(size > 1".parse::<Filter>().is_err());
+    assert!("size > 1 tag == ''".parse::<Filter>().is_err());
+    assert!("name == 'unterminated".parse::<Filter>().is_err());
+  }
+}
@@ -0,0 +1,26 @@
+pub fn row<const N: usize>(fields: [&str; N]) -> String {
+  fields.map(escape).join(",")
+}
+
+fn escape(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn plain_fields_are_left_alone() {
+    assert_eq!(row(["main.xml", "main_xml", "4096"]), "main.xml,main_xml,4096");
+  }
+
+  #[test]
+  fn special_fields_are_quoted() {
+    assert_eq!(row(["a,b", "say \"hi\"", "two\nlines", ""]), "\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",");
+  }
+}
@@ -0,0 +1,26 @@
+pub fn format(value: &serde_json::Value, compact: bool) -> String {
+  if compact {
+    format!("{value}")
+  } else {
+    format!("{value:#}")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compact_output_is_a_single_line() {
+    let value = serde_json::json!({ "entry": "main.xml", "sizes": [1, 2] });
+
+    assert_eq!(format(&value, true), r#"{"entry":"main.xml","sizes":[1,2]}"#);
+  }
+
+  #[test]
+  fn pretty_output_is_indented() {
+    let value = serde_json::json!({ "entry": "main.xml", "sizes": [1, 2] });
+
+    assert_eq!(format(&value, false), "{\n  \"entry\": \"main.xml\",\n  \"sizes\": [\n    1,\n    2\n  ]\n}");
+  }
+}
@@ -1,65 +1,153 @@
+use super::csv;
+use super::progress::Progress;
 use orphism::{Error, Runtime};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Debug, Clone, clap::Parser)]
 #[remain::sorted]
 pub struct Load {
   #[arg(long, value_name = "FILENAME")]
   match_filename: Option<String>,
-  #[arg(long)]
+  #[arg(long, value_name = "N", help = "skip models nested more than N directories below the non-wildcard part of the pattern")]
+  max_depth: Option<usize>,
+  #[arg(long, help = "fully parse the .moc3 data of every loaded model and print it (slow on large trees)")]
   moc3: bool,
+  #[arg(long, conflicts_with = "moc3", help = "only check that models load and contain .moc3 data, without parsing it (the default)")]
+  no_moc_parse: bool,
+  #[arg(
+    long,
+    value_name = "FILE",
+    help = "write an inventory of discovered models to a CSV file",
+    long_help = "write an inventory of discovered models (path, name, moc3 size, and load status) to a CSV file. Models that fail to load are recorded in the inventory and the remaining models are still loaded, but the command exits with an error at the end."
+  )]
+  output_csv: Option<PathBuf>,
   #[arg(long, value_name = "GLOB", default_value = "./assets/**/*.model3.json")]
   pattern: String,
+  #[arg(long, value_name = "FILE", help = "write progress events to a file as newline-delimited JSON")]
+  progress_to: Option<PathBuf>,
+  #[arg(long, value_name = "WHEN", value_parser = parse_time, help = "only load models modified since WHEN (a duration like 7d, or a date like 2024-01-31)")]
+  since: Option<SystemTime>,
+  #[arg(long, value_name = "WHEN", value_parser = parse_time, help = "only load models modified until WHEN (a duration like 7d, or a date like 2024-01-31)")]
+  until: Option<SystemTime>,
 }
 
 impl Load {
   pub fn execute(self) -> anyhow::Result<()> {
     let Self {
       match_filename: only_filename,
+      max_depth,
       moc3,
+      no_moc_parse: _,
+      output_csv,
       pattern,
+      progress_to,
+      since,
+      until,
     } = self;
 
     let mut models = Vec::new();
+    let mut inventory = Vec::new();
+    let mut failed = 0usize;
+
+    let load_model = |root: &Path, model: &Path| -> anyhow::Result<_> {
+      log::debug!("attempting to load directory {root:?}");
+      let runtime = match Runtime::new_from_runtime_path(root.to_owned()) {
+        Ok(runtime) => {
+          log::info!("loaded model from directory {root:?}");
+          runtime
+        }
+        Err(Error::RuntimePathContainsMultipleModels(_)) => {
+          log::debug!("failed because directory contains multiple models, attempting to load single model");
+          match Runtime::new_from_model_path(model.to_owned()) {
+            Ok(runtime) => {
+              log::info!("loaded model from file: {model:?}");
+              runtime
+            }
+            Err(error) => Err(error)?,
+          }
+        }
+        Err(error) => Err(error)?,
+      };
+      let model = runtime.load_model()?;
+      if model.data.is_empty() {
+        anyhow::bail!("model loaded from {root:?} has no .moc3 data");
+      }
+      Ok(model)
+    };
 
     log::info!("looking for files matching {pattern:?}");
 
-    for model in glob::glob(&pattern)? {
-      let model = model?;
+    let root_depth = depth(&glob_root(&pattern));
+
+    let paths = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+    let mut progress = progress_to.as_deref().map(|path| Progress::create(path, paths.len())).transpose()?;
 
+    let selected = |model: &Path| -> anyhow::Result<bool> {
       if let (Some(file_name), Some(target)) = (model.file_name(), only_filename.as_ref()) {
         if !file_name.to_string_lossy().eq(target) {
           log::debug!("skipping {file_name:?} because it does not match {target:?}");
-          continue;
+          return Ok(false);
         }
       }
 
-      log::debug!("found {model:?}");
+      if let Some(max_depth) = max_depth {
+        let nesting = depth(model).saturating_sub(root_depth + 1);
+        if nesting > max_depth {
+          log::debug!("skipping {model:?} because it is nested {nesting} directories deep");
+          return Ok(false);
+        }
+      }
 
-      if let Some(root) = model.as_path().parent() {
-        log::debug!("attempting to load directory {root:?}");
-        let runtime = match Runtime::new_from_runtime_path(root.to_owned()) {
-          Ok(runtime) => {
-            log::info!("loaded model from directory {root:?}");
-            runtime
-          }
-          Err(Error::RuntimePathContainsMultipleModels(_)) => {
-            log::debug!("failed because directory contains multiple models, attempting to load single model");
-            match Runtime::new_from_model_path(model.clone()) {
-              Ok(runtime) => {
-                log::info!("loaded model from file: {model:?}");
-                runtime
+      if since.is_some() || until.is_some() {
+        let modified = model.metadata()?.modified()?;
+        if since.is_some_and(|since| modified < since) || until.is_some_and(|until| modified > until) {
+          log::debug!("skipping {model:?} because it was not modified in the requested window");
+          return Ok(false);
+        }
+      }
+
+      Ok(true)
+    };
+
+    for model in paths {
+      if selected(&model)? {
+        log::debug!("found {model:?}");
+
+        if let Some(root) = model.as_path().parent() {
+          match load_model(root, &model) {
+            Ok(loaded) => {
+              if output_csv.is_some() {
+                inventory.push(inventory_row(&model, Some(&loaded.data), "loaded"));
               }
-              Err(error) => Err(error)?,
+              models.push(loaded);
             }
+            Err(error) if output_csv.is_some() => {
+              log::error!("failed to load {model:?}: {error:#}");
+              failed += 1;
+              inventory.push(inventory_row(&model, None, &format!("failed: {error:#}")));
+            }
+            Err(error) => return Err(error),
           }
-          Err(error) => Err(error)?,
-        };
-        let model = runtime.load_model()?;
-        models.push(model);
+        }
+      }
+
+      if let Some(progress) = progress.as_mut() {
+        progress.advance(&model.to_string_lossy())?;
       }
     }
 
-    log::info!("successfully loaded {} models", models.len());
+    let models_loaded = models.len();
+    log::info!("successfully loaded {models_loaded} models");
+
+    if let Some(path) = output_csv {
+      let mut contents = String::from("path,name,moc3_size,status\n");
+      for row in inventory {
+        contents.push_str(&row);
+        contents.push('\n');
+      }
+      std::fs::write(path, contents)?;
+    }
 
     if moc3 {
       log::info!("attempting to parse .moc3 data from all loaded models");
@@ -69,8 +157,92 @@ impl Load {
 
         println!("{model:#?}");
       }
+
+      log::info!("successfully parsed .moc3 data from {models_loaded} models");
+    }
+
+    if failed > 0 {
+      anyhow::bail!("failed to load {failed} of {} models", models_loaded + failed);
     }
 
     Ok(())
   }
 }
+
+fn parse_time(value: &str) -> anyhow::Result<SystemTime> {
+  if let Ok(duration) = humantime::parse_duration(value) {
+    return SystemTime::now().checked_sub(duration).ok_or_else(|| anyhow::anyhow!("{value:?} is too far in the past"));
+  }
+
+  let value = match value.len() {
+    10 => format!("{value} 00:00:00"),
+    _ => value.to_string(),
+  };
+
+  Ok(humantime::parse_rfc3339_weak(&value)?)
+}
+
+fn inventory_row(path: &Path, moc3: Option<&[u8]>, status: &str) -> String {
+  let name = path.file_name().map(|name| name.to_string_lossy().trim_end_matches(".model3.json").to_string()).unwrap_or_default();
+  let size = moc3.map(|data| data.len().to_string()).unwrap_or_default();
+
+  csv::row([
+    &*path.to_string_lossy(),
+    name.as_str(),
+    size.as_str(),
+    status,
+  ])
+}
+
+fn glob_root(pattern: &str) -> PathBuf {
+  Path::new(pattern)
+    .components()
+    .take_while(|component| !component.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+    .collect()
+}
+
+fn depth(path: &Path) -> usize {
+  path.components().filter(|component| *component != Component::CurDir).count()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::{Duration, UNIX_EPOCH};
+
+  #[test]
+  fn parses_relative_durations() {
+    let since = parse_time("7d").unwrap();
+    let age = SystemTime::now().duration_since(since).unwrap();
+
+    assert!(age >= Duration::from_secs(7 * 24 * 60 * 60));
+    assert!(age < Duration::from_secs(7 * 24 * 60 * 60 + 60));
+  }
+
+  #[test]
+  fn parses_absolute_dates() {
+    let midnight = UNIX_EPOCH + Duration::from_secs(1_704_153_600);
+
+    assert_eq!(parse_time("2024-01-02").unwrap(), midnight);
+    assert_eq!(parse_time("2024-01-02 03:04:05").unwrap(), midnight + Duration::from_secs(3 * 3600 + 4 * 60 + 5));
+  }
+
+  #[test]
+  fn rejects_unparseable_times() {
+    assert!(parse_time("last tuesday").is_err());
+  }
+
+  #[test]
+  fn glob_root_stops_at_the_first_wildcard() {
+    assert_eq!(glob_root("./assets/**/*.model3.json"), Path::new("./assets"));
+    assert_eq!(glob_root("assets/models/*/model.model3.json"), Path::new("assets/models"));
+    assert_eq!(glob_root("**/*.model3.json"), Path::new(""));
+  }
+
+  #[test]
+  fn depth_ignores_leading_current_directory() {
+    assert_eq!(depth(Path::new("./assets")), 1);
+    assert_eq!(depth(Path::new("assets/a/b/model.model3.json")), 4);
+    assert_eq!(depth(Path::new("./assets/a/b/model.model3.json")), 4);
+  }
+}
@@ -0,0 +1,72 @@
+use std::{
+  io,
+  path::{Path, PathBuf},
+  sync::{Mutex, MutexGuard, PoisonError},
+};
+
+// files written with --atomic, tracked so that --timeout can remove them before exiting
+pub static PARTIAL_FILES: PartialFiles = PartialFiles::new();
+
+pub struct PartialFiles(Mutex<Vec<PathBuf>>);
+
+impl PartialFiles {
+  const fn new() -> Self {
+    Self(Mutex::new(Vec::new()))
+  }
+
+  pub fn create<T>(&self, partial: &Path, create: impl FnOnce(&Path) -> io::Result<T>) -> io::Result<T> {
+    let mut partials = self.lock();
+    let created = create(partial)?;
+    partials.push(partial.to_owned());
+    Ok(created)
+  }
+
+  pub fn rename(&self, partial: &Path, path: &Path) -> io::Result<()> {
+    let mut partials = self.lock();
+    partials.retain(|tracked| tracked != partial);
+    std::fs::rename(partial, path)
+  }
+
+  pub fn remove(&self, partial: &Path) {
+    let mut partials = self.lock();
+    partials.retain(|tracked| tracked != partial);
+    let _ = std::fs::remove_file(partial);
+  }
+
+  // the returned guard keeps new partial files from being created (or renamed into place) until it is dropped
+  pub fn remove_all(&self) -> MutexGuard<'_, Vec<PathBuf>> {
+    let mut partials = self.lock();
+    for partial in partials.drain(..) {
+      if let Err(error) = std::fs::remove_file(&partial) {
+        log::warn!("failed to remove partial file {partial:?}: {error}");
+      }
+    }
+    partials
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Vec<PathBuf>> {
+    self.0.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn remove_all_deletes_only_unfinished_files() {
+    let directory = tempfile::tempdir().unwrap();
+    let partials = PartialFiles::new();
+    let finished = directory.path().join(".finished.partial");
+    let unfinished = directory.path().join(".unfinished.partial");
+
+    for partial in [&finished, &unfinished] {
+      partials.create(partial, |partial| std::fs::write(partial, b"data")).unwrap();
+    }
+    partials.rename(&finished, &directory.path().join("finished")).unwrap();
+
+    assert!(partials.remove_all().is_empty());
+    assert!(!unfinished.exists());
+    assert!(directory.path().join("finished").exists());
+  }
+}
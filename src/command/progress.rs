@@ -0,0 +1,51 @@
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::Path,
+};
+
+pub struct Progress {
+  done: usize,
+  total: usize,
+  writer: BufWriter<File>,
+}
+
+impl Progress {
+  pub fn create(path: &Path, total: usize) -> anyhow::Result<Self> {
+    let writer = BufWriter::new(File::create(path)?);
+    Ok(Self { done: 0, total, writer })
+  }
+
+  pub fn advance(&mut self, current: &str) -> anyhow::Result<()> {
+    self.done += 1;
+    let event = serde_json::json!({ "done": self.done, "total": self.total, "current": current });
+    writeln!(self.writer, "{event}")?;
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_every_entry_once() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+
+    let mut progress = Progress::create(file.path(), 3).unwrap();
+    for current in ["a", "b", "c"] {
+      progress.advance(current).unwrap();
+    }
+
+    let events = std::fs::read_to_string(file.path()).unwrap();
+    let events = events.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+
+    assert_eq!(events.len(), 3);
+    for (index, event) in events.iter().enumerate() {
+      assert_eq!(event["done"], index + 1);
+      assert_eq!(event["total"], 3);
+    }
+    assert_eq!(events[2]["current"], "c");
+  }
+}